@@ -0,0 +1,210 @@
+//! Symbolic names of well-known known folder IDs, such as `FOLDERID_Downloads`, and of
+//! folder type IDs, such as `FOLDERTYPEID_Documents`.
+
+use windows::core::GUID;
+use windows::Win32::UI::Shell::*;
+
+/// Pairs of `FOLDERID_*` constants' names and the GUIDs they stand for.
+///
+/// Some GUIDs have more than one name, such as `FOLDERID_OneDrive` and its older name
+/// `FOLDERID_SkyDrive`. The name that appears first is the one that is used.
+const NAMED_FOLDER_IDS: &[(&str, GUID)] = &named!(
+    FOLDERID_AccountPictures,
+    FOLDERID_AddNewPrograms,
+    FOLDERID_AdminTools,
+    FOLDERID_AllAppMods,
+    FOLDERID_AppCaptures,
+    FOLDERID_AppDataDesktop,
+    FOLDERID_AppDataDocuments,
+    FOLDERID_AppDataFavorites,
+    FOLDERID_AppDataProgramData,
+    FOLDERID_AppUpdates,
+    FOLDERID_ApplicationShortcuts,
+    FOLDERID_AppsFolder,
+    FOLDERID_CDBurning,
+    FOLDERID_CameraRoll,
+    FOLDERID_CameraRollLibrary,
+    FOLDERID_ChangeRemovePrograms,
+    FOLDERID_CommonAdminTools,
+    FOLDERID_CommonOEMLinks,
+    FOLDERID_CommonPrograms,
+    FOLDERID_CommonStartMenu,
+    FOLDERID_CommonStartMenuPlaces,
+    FOLDERID_CommonStartup,
+    FOLDERID_CommonTemplates,
+    FOLDERID_ComputerFolder,
+    FOLDERID_ConflictFolder,
+    FOLDERID_ConnectionsFolder,
+    FOLDERID_Contacts,
+    FOLDERID_ControlPanelFolder,
+    FOLDERID_Cookies,
+    FOLDERID_CurrentAppMods,
+    FOLDERID_Desktop,
+    FOLDERID_DevelopmentFiles,
+    FOLDERID_Device,
+    FOLDERID_DeviceMetadataStore,
+    FOLDERID_Documents,
+    FOLDERID_DocumentsLibrary,
+    FOLDERID_Downloads,
+    FOLDERID_Favorites,
+    FOLDERID_Fonts,
+    FOLDERID_GameTasks,
+    FOLDERID_Games,
+    FOLDERID_History,
+    FOLDERID_HomeGroup,
+    FOLDERID_HomeGroupCurrentUser,
+    FOLDERID_ImplicitAppShortcuts,
+    FOLDERID_InternetCache,
+    FOLDERID_InternetFolder,
+    FOLDERID_Libraries,
+    FOLDERID_Links,
+    FOLDERID_LocalAppData,
+    FOLDERID_LocalAppDataLow,
+    FOLDERID_LocalDocuments,
+    FOLDERID_LocalDownloads,
+    FOLDERID_LocalMusic,
+    FOLDERID_LocalPictures,
+    FOLDERID_LocalStorage,
+    FOLDERID_LocalVideos,
+    FOLDERID_LocalizedResourcesDir,
+    FOLDERID_Music,
+    FOLDERID_MusicLibrary,
+    FOLDERID_NetHood,
+    FOLDERID_NetworkFolder,
+    FOLDERID_Objects3D,
+    FOLDERID_OneDrive,
+    FOLDERID_OriginalImages,
+    FOLDERID_PhotoAlbums,
+    FOLDERID_Pictures,
+    FOLDERID_PicturesLibrary,
+    FOLDERID_Playlists,
+    FOLDERID_PrintHood,
+    FOLDERID_PrintersFolder,
+    FOLDERID_Profile,
+    FOLDERID_ProgramData,
+    FOLDERID_ProgramFiles,
+    FOLDERID_ProgramFilesCommon,
+    FOLDERID_ProgramFilesCommonX64,
+    FOLDERID_ProgramFilesCommonX86,
+    FOLDERID_ProgramFilesX64,
+    FOLDERID_ProgramFilesX86,
+    FOLDERID_Programs,
+    FOLDERID_Public,
+    FOLDERID_PublicDesktop,
+    FOLDERID_PublicDocuments,
+    FOLDERID_PublicDownloads,
+    FOLDERID_PublicGameTasks,
+    FOLDERID_PublicLibraries,
+    FOLDERID_PublicMusic,
+    FOLDERID_PublicPictures,
+    FOLDERID_PublicRingtones,
+    FOLDERID_PublicUserTiles,
+    FOLDERID_PublicVideos,
+    FOLDERID_QuickLaunch,
+    FOLDERID_Recent,
+    FOLDERID_RecordedCalls,
+    FOLDERID_RecordedTVLibrary,
+    FOLDERID_RecycleBinFolder,
+    FOLDERID_ResourceDir,
+    FOLDERID_RetailDemo,
+    FOLDERID_Ringtones,
+    FOLDERID_RoamedTileImages,
+    FOLDERID_RoamingAppData,
+    FOLDERID_RoamingTiles,
+    FOLDERID_SEARCH_CSC,
+    FOLDERID_SEARCH_MAPI,
+    FOLDERID_SampleMusic,
+    FOLDERID_SamplePictures,
+    FOLDERID_SamplePlaylists,
+    FOLDERID_SampleVideos,
+    FOLDERID_SavedGames,
+    FOLDERID_SavedPictures,
+    FOLDERID_SavedPicturesLibrary,
+    FOLDERID_SavedSearches,
+    FOLDERID_Screenshots,
+    FOLDERID_SearchHistory,
+    FOLDERID_SearchHome,
+    FOLDERID_SearchTemplates,
+    FOLDERID_SendTo,
+    FOLDERID_SidebarDefaultParts,
+    FOLDERID_SidebarParts,
+    FOLDERID_SkyDrive,
+    FOLDERID_SkyDriveCameraRoll,
+    FOLDERID_SkyDriveDocuments,
+    FOLDERID_SkyDriveMusic,
+    FOLDERID_SkyDrivePictures,
+    FOLDERID_StartMenu,
+    FOLDERID_StartMenuAllPrograms,
+    FOLDERID_Startup,
+    FOLDERID_SyncManagerFolder,
+    FOLDERID_SyncResultsFolder,
+    FOLDERID_SyncSetupFolder,
+    FOLDERID_System,
+    FOLDERID_SystemX86,
+    FOLDERID_Templates,
+    FOLDERID_UserPinned,
+    FOLDERID_UserProfiles,
+    FOLDERID_UserProgramFiles,
+    FOLDERID_UserProgramFilesCommon,
+    FOLDERID_UsersFiles,
+    FOLDERID_UsersLibraries,
+    FOLDERID_Videos,
+    FOLDERID_VideosLibrary,
+    FOLDERID_Windows,
+);
+
+/// Pairs of `FOLDERTYPEID_*` constants' names and the GUIDs they stand for.
+const NAMED_FOLDER_TYPE_IDS: &[(&str, GUID)] = &named!(
+    FOLDERTYPEID_AccountPictures,
+    FOLDERTYPEID_Communications,
+    FOLDERTYPEID_CompressedFolder,
+    FOLDERTYPEID_Contacts,
+    FOLDERTYPEID_ControlPanelCategory,
+    FOLDERTYPEID_ControlPanelClassic,
+    FOLDERTYPEID_Documents,
+    FOLDERTYPEID_Downloads,
+    FOLDERTYPEID_Games,
+    FOLDERTYPEID_Generic,
+    FOLDERTYPEID_GenericLibrary,
+    FOLDERTYPEID_GenericSearchResults,
+    FOLDERTYPEID_Invalid,
+    FOLDERTYPEID_Music,
+    FOLDERTYPEID_NetworkExplorer,
+    FOLDERTYPEID_OpenSearch,
+    FOLDERTYPEID_OtherUsers,
+    FOLDERTYPEID_Pictures,
+    FOLDERTYPEID_Printers,
+    FOLDERTYPEID_PublishedItems,
+    FOLDERTYPEID_RecordedTV,
+    FOLDERTYPEID_RecycleBin,
+    FOLDERTYPEID_SavedGames,
+    FOLDERTYPEID_SearchConnector,
+    FOLDERTYPEID_SearchHome,
+    FOLDERTYPEID_Searches,
+    FOLDERTYPEID_SoftwareExplorer,
+    FOLDERTYPEID_StartMenu,
+    FOLDERTYPEID_StorageProviderDocuments,
+    FOLDERTYPEID_StorageProviderGeneric,
+    FOLDERTYPEID_StorageProviderMusic,
+    FOLDERTYPEID_StorageProviderPictures,
+    FOLDERTYPEID_StorageProviderVideos,
+    FOLDERTYPEID_UserFiles,
+    FOLDERTYPEID_UsersLibraries,
+    FOLDERTYPEID_Videos,
+);
+
+/// Get the name of the `FOLDERID_*` constant for a known folder ID, if there is one.
+pub fn folder_id_name(id: &GUID) -> Option<&'static str> {
+    NAMED_FOLDER_IDS
+        .iter()
+        .find(|(_, named_id)| named_id == id)
+        .map(|(name, _)| *name)
+}
+
+/// Get the name of the `FOLDERTYPEID_*` constant for a folder type ID, if there is one.
+pub fn folder_type_id_name(id: &GUID) -> Option<&'static str> {
+    NAMED_FOLDER_TYPE_IDS
+        .iter()
+        .find(|(_, named_id)| named_id == id)
+        .map(|(name, _)| *name)
+}
@@ -0,0 +1,1230 @@
+//! Retrieve the special "known folders" on a Windows system, and their locations.
+//!
+//! See [Known Folders](https://learn.microsoft.com/en-us/windows/win32/shell/known-folders).
+
+/// Makes an array of pairs of each name as a string with the resolved name.
+///
+/// This is used to make tables of constants' names and values. It is exported only so the
+/// `knfo` program can use it too.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! named {
+    ($($ident:ident),* $(,)?) => {
+        [$(
+            (stringify!($ident), $ident),
+        )*]
+    };
+}
+
+mod folder_ids;
+
+pub use folder_ids::{folder_id_name, folder_type_id_name};
+
+use core::ffi::c_void;
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use windows::core::{Error as WindowsError, GUID, HRESULT, HSTRING, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{
+    ERROR_BAD_NETPATH, ERROR_BAD_NET_RESP, ERROR_CONNECTION_ABORTED, ERROR_DEV_NOT_EXIST,
+    ERROR_HOST_UNREACHABLE, ERROR_INVALID_DATA, ERROR_NETNAME_DELETED, ERROR_NETWORK_BUSY,
+    ERROR_NETWORK_UNREACHABLE, ERROR_REM_NOT_LIST, ERROR_SEM_TIMEOUT, ERROR_UNEXP_NET_ERR,
+    RPC_E_CHANGED_MODE, S_FALSE, WIN32_ERROR,
+};
+use windows::Win32::System::Com::{
+    CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, CLSCTX_INPROC_SERVER, COINIT,
+    COINIT_APARTMENTTHREADED, COINIT_MULTITHREADED,
+};
+use windows::Win32::UI::Shell::{
+    IKnownFolder, IKnownFolderManager, KnownFolderManager, SHLoadIndirectString, KF_CATEGORY,
+    KF_CATEGORY_COMMON, KF_CATEGORY_FIXED, KF_CATEGORY_PERUSER, KF_CATEGORY_VIRTUAL,
+    KF_FLAG_CREATE, KF_FLAG_DEFAULT_PATH, KF_FLAG_DONT_VERIFY, KF_FLAG_INIT,
+    KNOWNFOLDER_DEFINITION, KNOWN_FOLDER_FLAG,
+};
+
+/// Guard type that initializes COM on the current thread and uninitializes it on drop.
+///
+/// Known folders are accessed through COM, so this must be alive while calling into
+/// the rest of this library. Callers that already manage COM themselves need not use it.
+///
+/// COM initialization is reference counted. If COM was already initialized on the thread
+/// in the same apartment, such as by another component, `CoInitializeEx` succeeds with
+/// `S_FALSE`. This guard still calls `CoUninitialize` on drop in that case, because each
+/// successful call to `CoInitializeEx`, *including* one returning `S_FALSE`, must be
+/// balanced by a call to `CoUninitialize`. Doing so only decrements the count, so COM
+/// remains initialized for the other component. See [`already_initialized`].
+///
+/// [`already_initialized`]: ComInit::already_initialized
+pub struct ComInit {
+    already_initialized: bool,
+}
+
+impl ComInit {
+    /// Initialize COM on the current thread in a single-threaded apartment (STA).
+    ///
+    /// This is usually the right choice for a thread that does not otherwise use COM. It
+    /// is also required on threads that use COM objects that are only usable from an STA,
+    /// such as most UI components. But it fails with `RPC_E_CHANGED_MODE` if the thread
+    /// has already joined the multithreaded apartment. In that case, use [`new_mta`].
+    ///
+    /// [`new_mta`]: ComInit::new_mta
+    pub fn new() -> Result<Self, WindowsError> {
+        Self::with_model(COINIT_APARTMENTTHREADED)
+    }
+
+    /// Initialize COM on the current thread in the multithreaded apartment (MTA).
+    ///
+    /// This is for threads that are, or will be, in the MTA, such as worker threads that
+    /// already use COM that way. COM objects can then be used from any thread in the MTA,
+    /// but calls may be made on them concurrently, and objects that require an STA are
+    /// accessed through proxies, which is slower. This fails with `RPC_E_CHANGED_MODE` if
+    /// the thread has already been initialized in an STA. In that case, use [`new`].
+    ///
+    /// [`new`]: ComInit::new
+    pub fn new_mta() -> Result<Self, WindowsError> {
+        Self::with_model(COINIT_MULTITHREADED)
+    }
+
+    fn with_model(model: COINIT) -> Result<Self, WindowsError> {
+        let apartment = if model == COINIT_MULTITHREADED {
+            "MTA"
+        } else {
+            "STA"
+        };
+        let hresult = unsafe { CoInitializeEx(None, model) };
+        if let Err(e) = hresult.ok() {
+            log::debug!(
+                "Can't initialize COM in {apartment}: {}",
+                describe_error(&e)
+            );
+            return Err(e);
+        }
+        log::debug!(
+            "Initialized COM in {apartment} (0x{:08X})",
+            hresult.0 as u32
+        );
+        Ok(Self {
+            already_initialized: hresult == S_FALSE,
+        })
+    }
+
+    /// Whether COM was already initialized on this thread when this guard was created.
+    ///
+    /// If so, then dropping the guard will not actually uninitialize COM, since something
+    /// else also holds it initialized.
+    pub fn already_initialized(&self) -> bool {
+        self.already_initialized
+    }
+
+    /// Consume the guard without uninitializing COM, leaving it initialized on this thread.
+    ///
+    /// Usually this is not needed, even in a host that manages COM itself: dropping the
+    /// guard only balances its own initialization, so it never uninitializes COM out from
+    /// under the host. (A host that already initializes COM on the thread need not use a
+    /// guard at all.) This is for when COM should stay initialized for the rest of the
+    /// thread's life, such as when the guard can't be kept alive as long as COM objects
+    /// obtained while it was held, or when the host expects the reference count it finds
+    /// to be left raised. The thread's COM reference count then stays incremented.
+    pub fn leak(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for ComInit {
+    fn drop(&mut self) {
+        // This is correct even if `already_initialized` is true. See the type's doc comment.
+        log::debug!("Uninitializing COM");
+        unsafe { CoUninitialize() };
+    }
+}
+
+/// Format an error's `HRESULT` in hex, such as `0x80070002`.
+pub fn hresult_hex(error: &WindowsError) -> String {
+    format!("0x{:08X}", error.code().0 as u32)
+}
+
+/// Describe an error as its message and its `HRESULT` in hex.
+///
+/// For example: `The system cannot find the file specified. (0x80070002)`
+pub fn describe_error(error: &WindowsError) -> String {
+    format!("{} ({})", error.message(), hresult_hex(error))
+}
+
+/// Free a `PWSTR` with `CoTaskMemFree`.
+fn co_free_pwstr(pwstr: PWSTR) {
+    unsafe { CoTaskMemFree(Some(pwstr.as_ptr().cast::<c_void>())) };
+}
+
+/// Convert a `PWSTR` to a `String`, replacing any invalid UTF-16 and warning about it.
+///
+/// A malformed string in one known folder should not prevent the others from being listed.
+/// The warning says which folder, by `id`, and which of its strings, by `field`, it is.
+fn to_string_lossy(pwstr: PWSTR, id: &GUID, field: &str) -> String {
+    from_utf16_lossy(unsafe { pwstr.as_wide() }, id, field)
+}
+
+/// Convert UTF-16 to a `String`, replacing any invalid UTF-16 and warning about it.
+///
+/// The warning is as in [`to_string_lossy`].
+fn from_utf16_lossy(wide: &[u16], id: &GUID, field: &str) -> String {
+    match String::from_utf16(wide) {
+        Ok(text) => text,
+        Err(_) => {
+            log::warn!(
+                "Known folder {} has invalid UTF-16 in its {field}",
+                guid_to_string(id),
+            );
+            String::from_utf16_lossy(wide)
+        }
+    }
+}
+
+/// Convert a `PWSTR` that may be null to a `String`, or to `None` if it is null.
+///
+/// Invalid UTF-16 is replaced, with a warning, as in [`to_string_lossy`].
+fn to_optional_string(pwstr: PWSTR, id: &GUID, field: &str) -> Option<String> {
+    if pwstr.is_null() {
+        None
+    } else {
+        Some(to_string_lossy(pwstr, id, field))
+    }
+}
+
+/// Resolve an indirect string like `@%SystemRoot%\system32\shell32.dll,-21770`.
+///
+/// Strings that are not indirect strings are returned unchanged. Invalid UTF-16 in the
+/// result is replaced, with a warning, as in [`to_string_lossy`].
+fn load_indirect_string(source: PWSTR, id: &GUID, field: &str) -> Result<String, WindowsError> {
+    // SHLoadIndirectString truncates strings that don't fit, without saying how much room
+    // they need, so if one fills the buffer, we try again with a bigger buffer. No string
+    // resource is longer than the largest size tried.
+    const MAX_SIZE: usize = 1 << 16;
+    let mut size = 1024;
+    loop {
+        let mut buffer = vec![0u16; size];
+        unsafe { SHLoadIndirectString(PCWSTR(source.0), &mut buffer, None)? };
+        let len = buffer.iter().position(|&c| c == 0).unwrap_or(size);
+        if len + 1 < size || size >= MAX_SIZE {
+            return Ok(from_utf16_lossy(&buffer[..len], id, field));
+        }
+        size *= 2;
+    }
+}
+
+/// Owner of a `PWSTR` that must be freed with `CoTaskMemFree`.
+struct CoStr {
+    pwstr: PWSTR,
+}
+
+impl CoStr {
+    fn new(pwstr: PWSTR) -> Self {
+        Self { pwstr }
+    }
+
+    fn to_string_lossy(&self, id: &GUID, field: &str) -> String {
+        to_string_lossy(self.pwstr, id, field)
+    }
+}
+
+impl Drop for CoStr {
+    fn drop(&mut self) {
+        co_free_pwstr(self.pwstr);
+    }
+}
+
+/// Owner of `IKnownFolderManager::GetFolderIds` results.
+///
+/// On drop, this calls `CoTaskMemFree` on the block of GUIDs representing known folders.
+pub struct KnownFolderIds {
+    pkfid: *mut GUID,
+    count: u32,
+}
+
+impl KnownFolderIds {
+    pub fn new(kf_manager: &IKnownFolderManager) -> Result<Self, WindowsError> {
+        let mut pkfid = std::ptr::null_mut();
+        let mut count = 0;
+        unsafe { kf_manager.GetFolderIds(&mut pkfid, &mut count)? };
+        Ok(Self { pkfid, count })
+    }
+
+    /// The IDs, in the order the system listed them.
+    pub fn as_slice(&self) -> &[GUID] {
+        // With no IDs, the pointer may be null, which from_raw_parts doesn't allow.
+        if self.pkfid.is_null() {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.pkfid, self.count as usize) }
+    }
+
+    /// Copy the IDs, omitting any that appear more than once after their first appearance.
+    ///
+    /// The same ID is occasionally listed more than once, on systems whose registry
+    /// entries for known folders are in an unusual state.
+    pub fn to_unique_vec(&self) -> Vec<GUID> {
+        let mut seen = HashSet::new();
+        self.as_slice()
+            .iter()
+            .copied()
+            .filter(|id| seen.insert(*id))
+            .collect()
+    }
+}
+
+impl Drop for KnownFolderIds {
+    fn drop(&mut self) {
+        unsafe { CoTaskMemFree(Some(self.pkfid.cast::<c_void>())) };
+    }
+}
+
+/// Owner of a `KNOWNFOLDER_DEFINITION` that frees its dynamic strings on drop.
+pub struct KnownFolderDefinition {
+    fields: KNOWNFOLDER_DEFINITION,
+}
+
+impl KnownFolderDefinition {
+    pub fn of(folder: &IKnownFolder) -> Result<Self, WindowsError> {
+        let mut fields = KNOWNFOLDER_DEFINITION::default();
+        unsafe { folder.GetFolderDefinition(&mut fields)? };
+        Ok(Self { fields })
+    }
+
+    /// The raw fields. Their strings are only valid as long as `self` is.
+    pub fn fields(&self) -> &KNOWNFOLDER_DEFINITION {
+        &self.fields
+    }
+}
+
+impl Drop for KnownFolderDefinition {
+    fn drop(&mut self) {
+        // The windows crate does not provide FreeKnownFolderDefinitionFields, possibly
+        // due to it being an __inline function. This frees each of the fields that is a
+        // pointer to a string, which is equivalent to FreeKnownFolderDefinitionFields.
+        co_free_pwstr(self.fields.pszName);
+        co_free_pwstr(self.fields.pszDescription);
+        co_free_pwstr(self.fields.pszRelativePath);
+        co_free_pwstr(self.fields.pszParsingName);
+        co_free_pwstr(self.fields.pszTooltip);
+        co_free_pwstr(self.fields.pszLocalizedName);
+        co_free_pwstr(self.fields.pszIcon);
+        co_free_pwstr(self.fields.pszSecurity);
+    }
+}
+
+/// Format a GUID in the usual registry form, such as `{2B0F765D-C0E9-4171-908E-08A611B84FF6}`.
+pub fn guid_to_string(id: &GUID) -> String {
+    format!("{{{id:?}}}")
+}
+
+/// Parse a GUID in its usual hyphenated form, with or without enclosing braces.
+///
+/// This returns `None` if the text is not a well-formed GUID.
+pub fn guid_from_string(text: &str) -> Option<GUID> {
+    let bare = text
+        .strip_prefix('{')
+        .and_then(|rest| rest.strip_suffix('}'))
+        .unwrap_or(text);
+
+    let well_formed = bare.len() == 36
+        && bare.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        });
+    if !well_formed {
+        return None;
+    }
+
+    let value = u128::from_str_radix(&bare.replace('-', ""), 16).ok()?;
+    Some(GUID::from_u128(value))
+}
+
+/// The category of a known folder, which affects how, and whether, it has a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Category {
+    /// A virtual folder, which has no path in the filesystem.
+    Virtual,
+
+    /// A fixed folder, such as the Windows directory, that does not belong to a user.
+    Fixed,
+
+    /// A common folder, shared by all users.
+    Common,
+
+    /// A per-user folder, such as a user's Documents directory.
+    PerUser,
+}
+
+impl Category {
+    /// Get the category a `KF_CATEGORY` value represents, if it is one we recognize.
+    pub fn from_raw(raw: KF_CATEGORY) -> Option<Self> {
+        match raw {
+            KF_CATEGORY_VIRTUAL => Some(Self::Virtual),
+            KF_CATEGORY_FIXED => Some(Self::Fixed),
+            KF_CATEGORY_COMMON => Some(Self::Common),
+            KF_CATEGORY_PERUSER => Some(Self::PerUser),
+            _ => None,
+        }
+    }
+
+    /// Get the category with the given readable name, as returned by [`name`].
+    ///
+    /// [`name`]: Category::name
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Virtual, Self::Fixed, Self::Common, Self::PerUser]
+            .into_iter()
+            .find(|category| category.name() == name)
+    }
+
+    /// A readable name for the category, such as `PerUser`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Virtual => "Virtual",
+            Self::Fixed => "Fixed",
+            Self::Common => "Common",
+            Self::PerUser => "PerUser",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// A known folder name and ID, and either its retrieved path or an error.
+pub struct NamedPath {
+    pub name: String,
+
+    /// The localized display name, if the folder has one and it could be loaded.
+    pub localized_name: Option<String>,
+
+    pub id: GUID,
+
+    /// The name of the `FOLDERID_*` constant for `id`, or the GUID itself if there is none.
+    pub folderid_name: String,
+
+    /// The category, or `None` if the folder's definition has an unrecognized category.
+    pub category: Option<Category>,
+
+    /// The shell parsing name, such as `::{GUID}` for many virtual folders, if any.
+    pub parsing_name: Option<String>,
+
+    /// The ID of the parent known folder, if any.
+    pub parent_id: Option<GUID>,
+
+    /// The path relative to the parent known folder, such as `Downloads`, if any.
+    pub relative_path: Option<String>,
+
+    /// A description of the folder's purpose, if it has one.
+    pub description: Option<String>,
+
+    /// The tooltip text shown when hovering over the folder, if it has one that could be
+    /// loaded.
+    pub tooltip: Option<String>,
+
+    /// The icon resource reference, such as `%SystemRoot%\system32\imageres.dll,-184`, if
+    /// any. Environment variables in it are not expanded.
+    pub icon: Option<String>,
+
+    /// The security descriptor, in SDDL, to apply when the folder is created, if any.
+    ///
+    /// This is `None` if the folder has no security descriptor, which is common, and is
+    /// distinct from `Some` of an empty string.
+    pub security: Option<String>,
+
+    /// The folder type ID, which the shell uses to choose how to show the folder's contents,
+    /// if any. Many of these have `FOLDERTYPEID_*` names; see [`folder_type_id_name`].
+    pub folder_type: Option<GUID>,
+
+    /// The `KF_REDIRECTION_CAPABILITIES` bits saying whether, and why not, the folder can be
+    /// redirected, or the error from trying to get them.
+    ///
+    /// This is `None` unless [`LookupOptions::redirection_capabilities`] was set.
+    pub redirection_capabilities: Option<Result<u32, WindowsError>>,
+
+    /// The bytes of the folder's item ID list (PIDL), including the terminating empty item
+    /// ID, or the error from trying to get it. Many folders without paths have ID lists.
+    ///
+    /// This is obtained with the same flags as `try_path`, except `KF_FLAG_CREATE` and
+    /// `KF_FLAG_INIT`. It is `None` unless [`LookupOptions::id_list`] was set, since it can
+    /// take as long to get as the path.
+    pub id_list: Option<Result<Vec<u8>, WindowsError>>,
+
+    pub try_path: Result<String, WindowsError>,
+
+    /// The default path, as if the folder were not redirected, or an error.
+    ///
+    /// This is obtained with the same flags as `try_path`, except `KF_FLAG_CREATE` and
+    /// `KF_FLAG_INIT`, plus `KF_FLAG_DEFAULT_PATH` and `KF_FLAG_DONT_VERIFY`.
+    pub try_default_path: Result<String, WindowsError>,
+
+    /// Whether the path and default path were both obtained, and differ.
+    ///
+    /// The comparison is case-insensitive. Virtual folders are never considered redirected.
+    pub redirected: bool,
+
+    /// How long it took to look up the path (or fail to).
+    pub path_duration: Duration,
+}
+
+/// An error as it is serialized: its `HRESULT` in hex, such as `0x80070002`, and message.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ErrorRecord {
+    hresult: String,
+    message: String,
+}
+
+impl ErrorRecord {
+    fn of(error: &WindowsError) -> Self {
+        Self {
+            hresult: hresult_hex(error),
+            message: error.message(),
+        }
+    }
+
+    /// Recreate the error, if `hresult` is well formed.
+    fn to_error(&self) -> Option<WindowsError> {
+        let hex = self.hresult.strip_prefix("0x")?;
+        let code = u32::from_str_radix(hex, 16).ok()?;
+        Some(WindowsError::new(
+            HRESULT(code as i32),
+            self.message.as_str(),
+        ))
+    }
+}
+
+impl NamedPath {
+    /// Get a form of this that serializes with all of the folder's definition fields.
+    ///
+    /// See [`DetailedNamedPath`].
+    pub fn detailed(&self) -> DetailedNamedPath<'_> {
+        DetailedNamedPath(self)
+    }
+
+    /// Serialize the usual fields, plus the definition fields if `detailed` is true.
+    fn serialize_with<S: Serializer>(
+        &self,
+        serializer: S,
+        detailed: bool,
+    ) -> Result<S::Ok, S::Error> {
+        let (path, error) = match &self.try_path {
+            Ok(path) => (Some(path), None),
+            Err(e) => (None, Some(ErrorRecord::of(e))),
+        };
+
+        let len = 6 + usize::from(error.is_some()) + if detailed { 9 } else { 0 };
+        let mut state = serializer.serialize_struct("NamedPath", len)?;
+        state.serialize_field("name", &self.name)?;
+        state.serialize_field("guid", &guid_to_string(&self.id))?;
+        state.serialize_field("folderid", &self.folderid_name)?;
+        state.serialize_field("category", &self.category.map(Category::name))?;
+        state.serialize_field("path", &path)?;
+        state.serialize_field("redirected", &self.redirected)?;
+        match error {
+            Some(error) => state.serialize_field("error", &error)?,
+            None => state.skip_field("error")?,
+        }
+        if detailed {
+            state.serialize_field("localizedName", &self.localized_name)?;
+            state.serialize_field("description", &self.description)?;
+            state.serialize_field("relativePath", &self.relative_path)?;
+            state.serialize_field("parsingName", &self.parsing_name)?;
+            state.serialize_field("tooltip", &self.tooltip)?;
+            state.serialize_field("icon", &self.icon)?;
+            state.serialize_field("security", &self.security)?;
+            state.serialize_field("parent", &self.parent_id.as_ref().map(guid_to_string))?;
+            state.serialize_field("folderType", &self.folder_type.as_ref().map(guid_to_string))?;
+        }
+        state.end()
+    }
+}
+
+impl Serialize for NamedPath {
+    /// Serializes as an object with `name`, `guid`, `folderid`, `category`, `path`, and
+    /// `redirected`, plus `error` if the lookup failed. The `error` is an object with the
+    /// `HRESULT` as a hex string, `hresult`, and the `message`.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.serialize_with(serializer, false)
+    }
+}
+
+/// A [`NamedPath`] that serializes with all of its known folder's definition fields.
+///
+/// Besides the fields a `NamedPath` serializes with, this has `localizedName`,
+/// `description`, `relativePath`, `parsingName`, `tooltip`, `icon`, `security`, `parent`,
+/// and `folderType`, each of which is `null` if the folder doesn't have it. The `parent`
+/// and `folderType` are GUIDs. This deserializes as a `NamedPath` like any other.
+pub struct DetailedNamedPath<'a>(&'a NamedPath);
+
+impl Serialize for DetailedNamedPath<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_with(serializer, true)
+    }
+}
+
+/// The fields of a [`NamedPath`] as it is serialized, for deserializing it.
+#[derive(serde::Deserialize)]
+struct NamedPathRecord {
+    name: String,
+    guid: String,
+    folderid: Option<String>,
+    category: Option<String>,
+    path: Option<String>,
+    #[serde(default)]
+    redirected: bool,
+    error: Option<ErrorRecord>,
+}
+
+impl<'de> Deserialize<'de> for NamedPath {
+    /// Deserializes from the form produced by serializing, such as a saved snapshot.
+    ///
+    /// A failed lookup's error is recreated from its `error` object. Fields that are not
+    /// serialized are `None`, those that are just results, such as `try_default_path`, are
+    /// empty errors, and `path_duration` is zero.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let record = NamedPathRecord::deserialize(deserializer)?;
+
+        let id = guid_from_string(&record.guid)
+            .ok_or_else(|| de::Error::custom(format!("malformed GUID: {}", record.guid)))?;
+
+        let try_path = match (record.path, record.error) {
+            (Some(path), None) => Ok(path),
+            (None, Some(error)) => Err(error.to_error().ok_or_else(|| {
+                de::Error::custom(format!("malformed HRESULT: {}", error.hresult))
+            })?),
+            _ => {
+                return Err(de::Error::custom(
+                    "need either a path, or an error with its HRESULT",
+                ))
+            }
+        };
+
+        Ok(Self {
+            name: record.name,
+            localized_name: None,
+            id,
+            folderid_name: record.folderid.unwrap_or_else(|| guid_to_string(&id)),
+            category: record.category.as_deref().and_then(Category::from_name),
+            parsing_name: None,
+            parent_id: None,
+            relative_path: None,
+            description: None,
+            tooltip: None,
+            icon: None,
+            security: None,
+            folder_type: None,
+            redirection_capabilities: None,
+            id_list: None,
+            try_path,
+            try_default_path: Err(WindowsError::empty()),
+            redirected: record.redirected,
+            path_duration: Duration::ZERO,
+        })
+    }
+}
+
+impl fmt::Display for NamedPath {
+    /// Formats as the name and path, or the error message in brackets, on a single line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.try_path {
+            Ok(path) => write!(f, "{}  {path}", self.name),
+            Err(e) => write!(f, "{}  [{}]", self.name, e.message()),
+        }
+    }
+}
+
+/// Remove the flags that create folders, for lookups other than of the path itself.
+///
+/// Only the path lookup the caller asked for should ever create a folder. Otherwise, for
+/// example, the default path lookup could create a folder at the default location of a
+/// redirected folder.
+fn without_creation(flags: KNOWN_FOLDER_FLAG) -> KNOWN_FOLDER_FLAG {
+    KNOWN_FOLDER_FLAG(flags.0 & !(KF_FLAG_CREATE.0 | KF_FLAG_INIT.0))
+}
+
+/// Get a known folder's path, or the error from trying to get it.
+///
+/// Invalid UTF-16 is replaced, with a warning, as in [`to_string_lossy`].
+fn try_get_path(
+    folder: &IKnownFolder,
+    id: &GUID,
+    flags: KNOWN_FOLDER_FLAG,
+    field: &str,
+) -> Result<String, WindowsError> {
+    let pwstr = unsafe { folder.GetPath(flags.0 as u32)? };
+    Ok(CoStr::new(pwstr).to_string_lossy(id, field))
+}
+
+/// Get a known folder's item ID list (PIDL), as bytes, or the error from trying to get it.
+fn try_get_id_list(
+    folder: &IKnownFolder,
+    flags: KNOWN_FOLDER_FLAG,
+) -> Result<Vec<u8>, WindowsError> {
+    let pidl = unsafe { folder.GetIDList(flags.0 as u32)? }.cast::<u8>();
+    if pidl.is_null() {
+        return Ok(vec![]);
+    }
+
+    // Each item ID starts with its size, including the size field itself. An item ID of
+    // size 0 ends the list. Sizes need not be aligned, so they are read unaligned. Any other
+    // size too small to hold the size field is malformed, and we can't safely go past it.
+    let mut len = 0;
+    let result = loop {
+        let cb = unsafe { pidl.add(len).cast::<u16>().read_unaligned() } as usize;
+        len += size_of::<u16>();
+        match cb {
+            0 => break Ok(unsafe { std::slice::from_raw_parts(pidl, len) }.to_vec()),
+            cb if cb < size_of::<u16>() => {
+                let code = HRESULT::from_win32(ERROR_INVALID_DATA.0);
+                break Err(WindowsError::new(code, "Malformed item ID list"));
+            }
+            cb => len += cb - size_of::<u16>(),
+        }
+    };
+
+    unsafe { CoTaskMemFree(Some(pidl.cast::<c_void>())) };
+    result
+}
+
+/// Network errors that may go away if a path lookup is retried, such as on a cold cache.
+const TRANSIENT_ERRORS: &[WIN32_ERROR] = &[
+    ERROR_REM_NOT_LIST,
+    ERROR_BAD_NETPATH,
+    ERROR_NETWORK_BUSY,
+    ERROR_DEV_NOT_EXIST,
+    ERROR_BAD_NET_RESP,
+    ERROR_UNEXP_NET_ERR,
+    ERROR_NETNAME_DELETED,
+    ERROR_SEM_TIMEOUT,
+    ERROR_NETWORK_UNREACHABLE,
+    ERROR_HOST_UNREACHABLE,
+    ERROR_CONNECTION_ABORTED,
+];
+
+/// How long to wait before the first retry. Each later retry waits this much longer.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Whether an error is one of the [`TRANSIENT_ERRORS`], as an `HRESULT`.
+fn is_transient(error: &WindowsError) -> bool {
+    TRANSIENT_ERRORS
+        .iter()
+        .any(|code| HRESULT::from_win32(code.0) == error.code())
+}
+
+/// Call `f`, and call it again up to `retries` more times while it fails transiently.
+fn with_retries<T>(
+    retries: u32,
+    mut f: impl FnMut() -> Result<T, WindowsError>,
+) -> Result<T, WindowsError> {
+    let mut result = f();
+    for attempt in 1..=retries {
+        match &result {
+            Err(e) if is_transient(e) => {
+                log::info!("Retrying after transient error: {}", describe_error(e));
+                std::thread::sleep(RETRY_DELAY * attempt);
+                result = f();
+            }
+            _ => break,
+        }
+    }
+    result
+}
+
+/// Get a known folder's name and either its path or an error from getting the path.
+///
+/// If looking up the path fails with a transient network error, it is retried up to
+/// `options.retries` times, waiting a little longer before each retry.
+fn get_named_path(
+    id: GUID,
+    folder: &IKnownFolder,
+    options: &LookupOptions,
+) -> Result<NamedPath, WindowsError> {
+    let LookupOptions { flags, retries, .. } = *options;
+    log::debug!("Looking up {}", guid_to_string(&id));
+    let definition = KnownFolderDefinition::of(folder)?;
+    let name = to_string_lossy(definition.fields.pszName, &id, "name");
+
+    // Most localized names are indirect strings referring to string resources.
+    // If one can't be loaded, we treat it as absent, so the name is used instead.
+    let localized_name = match definition.fields.pszLocalizedName {
+        pwstr if pwstr.is_null() => None,
+        pwstr => load_indirect_string(pwstr, &id, "localized name").ok(),
+    };
+
+    let folderid_name = folder_id_name(&id).map_or_else(|| guid_to_string(&id), String::from);
+    let category = Category::from_raw(definition.fields.category);
+    let parsing_name = to_optional_string(definition.fields.pszParsingName, &id, "parsing name");
+    let parent_id = Some(definition.fields.fidParent).filter(|id| *id != GUID::zeroed());
+    let relative_path = to_optional_string(definition.fields.pszRelativePath, &id, "relative path");
+    let description = to_optional_string(definition.fields.pszDescription, &id, "description");
+
+    // Tooltips, like localized names, are usually indirect strings.
+    let tooltip = match definition.fields.pszTooltip {
+        pwstr if pwstr.is_null() => None,
+        pwstr => load_indirect_string(pwstr, &id, "tooltip").ok(),
+    };
+    let icon = to_optional_string(definition.fields.pszIcon, &id, "icon");
+    let security = to_optional_string(definition.fields.pszSecurity, &id, "security descriptor");
+    let folder_type = Some(definition.fields.ftidType).filter(|id| *id != GUID::zeroed());
+
+    let redirection_capabilities = options
+        .redirection_capabilities
+        .then(|| unsafe { folder.GetRedirectionCapabilities() });
+    let id_list = options
+        .id_list
+        .then(|| try_get_id_list(folder, without_creation(flags)));
+
+    let start = Instant::now();
+    let try_path = with_retries(retries, || try_get_path(folder, &id, flags, "path"));
+    let path_duration = start.elapsed();
+    match &try_path {
+        Ok(_) => log::debug!("Got path of {name} in {path_duration:?}"),
+        Err(e) => log::info!(
+            "Can't get path of {name} after {path_duration:?}: {}",
+            describe_error(e)
+        ),
+    }
+    let try_default_path = try_get_path(
+        folder,
+        &id,
+        without_creation(flags) | KF_FLAG_DEFAULT_PATH | KF_FLAG_DONT_VERIFY,
+        "default path",
+    );
+    let redirected = match (&try_path, &try_default_path) {
+        (Ok(path), Ok(default)) => path.to_lowercase() != default.to_lowercase(),
+        _ => false,
+    };
+
+    Ok(NamedPath {
+        name,
+        localized_name,
+        id,
+        folderid_name,
+        category,
+        parsing_name,
+        parent_id,
+        relative_path,
+        description,
+        tooltip,
+        icon,
+        security,
+        folder_type,
+        redirection_capabilities,
+        id_list,
+        try_path,
+        try_default_path,
+        redirected,
+        path_duration,
+    })
+}
+
+/// Make a `NamedPath` for a known folder whose information could not be retrieved at all.
+///
+/// The name is the folder's `FOLDERID_*` constant name, if it has one, or otherwise its
+/// GUID. The error is used as both `try_path` and `try_default_path`, and in place of any
+/// other information that `options` ask for.
+fn failed_named_path(id: GUID, error: WindowsError, options: &LookupOptions) -> NamedPath {
+    let folderid_name = folder_id_name(&id).map_or_else(|| guid_to_string(&id), String::from);
+    NamedPath {
+        name: folderid_name.clone(),
+        localized_name: None,
+        id,
+        folderid_name,
+        category: None,
+        parsing_name: None,
+        parent_id: None,
+        relative_path: None,
+        description: None,
+        tooltip: None,
+        icon: None,
+        security: None,
+        folder_type: None,
+        redirection_capabilities: options.redirection_capabilities.then(|| Err(error.clone())),
+        id_list: options.id_list.then(|| Err(error.clone())),
+        try_path: Err(error.clone()),
+        try_default_path: Err(error),
+        redirected: false,
+        path_duration: Duration::ZERO,
+    }
+}
+
+/// How to look up known folders.
+///
+/// The default looks up paths with `KF_FLAG_DEFAULT`, never retries, and includes folders
+/// that can't be retrieved at all, with their errors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LookupOptions {
+    /// The flags to look up paths with.
+    pub flags: KNOWN_FOLDER_FLAG,
+
+    /// How many times to retry a path lookup that fails with a transient network error.
+    ///
+    /// Each retry waits a little longer than the one before. Other errors, such as a
+    /// directory not existing, are never retried.
+    pub retries: u32,
+
+    /// Whether to return the error for a folder that can't be retrieved at all.
+    ///
+    /// Otherwise, such a folder is included, with that error in place of its path, so
+    /// one problematic folder does not prevent the others from being listed. Errors
+    /// getting paths are never returned this way. This only affects functions that look
+    /// up many folders, since the others return such errors anyway.
+    pub fail_fast: bool,
+
+    /// Whether to get each folder's item ID list, as [`NamedPath::id_list`].
+    pub id_list: bool,
+
+    /// Whether to get each folder's redirection capabilities, as
+    /// [`NamedPath::redirection_capabilities`].
+    pub redirection_capabilities: bool,
+}
+
+/// Get the name and either path or error for the known folder with the given ID.
+///
+/// A folder that can't be retrieved at all is handled as `options.fail_fast` says.
+fn get_named_path_of(
+    kf_manager: &IKnownFolderManager,
+    id: &GUID,
+    options: &LookupOptions,
+) -> Result<NamedPath, WindowsError> {
+    let result = unsafe { kf_manager.GetFolder(id) }
+        .and_then(|folder| get_named_path(*id, &folder, options));
+    // Without fail_fast, this is not an error, since the folder is still listed with it.
+    if let Err(e) = &result {
+        let guid = guid_to_string(id);
+        let level = if options.fail_fast {
+            log::Level::Error
+        } else {
+            log::Level::Info
+        };
+        log::log!(
+            level,
+            "Can't retrieve known folder {guid}: {}",
+            describe_error(e)
+        );
+    }
+    match result {
+        Err(e) if !options.fail_fast => Ok(failed_named_path(*id, e, options)),
+        result => result,
+    }
+}
+
+/// Get names and either paths or errors for the known folders with the given IDs.
+///
+/// Folders that can't be retrieved at all are handled as `options.fail_fast` says.
+fn get_named_paths_of(
+    kf_manager: &IKnownFolderManager,
+    ids: &[GUID],
+    options: &LookupOptions,
+) -> Result<Vec<NamedPath>, WindowsError> {
+    ids.iter()
+        .map(|id| get_named_path_of(kf_manager, id, options))
+        .collect()
+}
+
+/// Create a known folder manager, through which known folders can be looked up.
+///
+/// The manager can be passed to [`get_named_paths`] and the other lookup functions any
+/// number of times, with any options, so it only has to be created once. It must only
+/// be used on the thread that created it.
+///
+/// COM must be initialized on the calling thread, such as by holding a [`ComInit`], and
+/// must stay initialized for as long as the manager is used.
+pub fn create_known_folder_manager() -> Result<IKnownFolderManager, WindowsError> {
+    unsafe { CoCreateInstance(&KnownFolderManager, None, CLSCTX_INPROC_SERVER) }
+}
+
+/// Get all known folder names and either paths or an error from getting the path.
+///
+/// Folders are looked up through `kf_manager`, such as from
+/// [`create_known_folder_manager`], as `options` say. All the work is done on the calling
+/// thread.
+pub fn get_named_paths(
+    kf_manager: &IKnownFolderManager,
+    options: &LookupOptions,
+) -> Result<Vec<NamedPath>, WindowsError> {
+    let ids = KnownFolderIds::new(kf_manager)?.to_unique_vec();
+    log::info!("Looking up {} known folders", ids.len());
+    get_named_paths_of(kf_manager, &ids, options)
+}
+
+/// Like [`get_named_paths`], but look up each folder only as the iterator reaches it.
+///
+/// The folders are enumerated first, so an error doing that is returned right away. Then
+/// each folder is looked up as it is reached, so callers can use each one as soon as it is
+/// found, or stop early without looking up the rest. The folders are in the same order as
+/// from [`get_named_paths`]. If `options.fail_fast` is true, a folder that can't be
+/// retrieved at all is yielded as its error, and iteration may continue after it.
+///
+/// The manager and options may be borrowed or owned. If owned, the iterator holds them.
+pub fn iter_named_paths<'a>(
+    kf_manager: impl Borrow<IKnownFolderManager> + 'a,
+    options: impl Borrow<LookupOptions> + 'a,
+) -> Result<impl Iterator<Item = Result<NamedPath, WindowsError>> + 'a, WindowsError> {
+    let ids = KnownFolderIds::new(kf_manager.borrow())?.to_unique_vec();
+    log::info!("Looking up {} known folders, one at a time", ids.len());
+    Ok(ids
+        .into_iter()
+        .map(move |id| get_named_path_of(kf_manager.borrow(), &id, options.borrow())))
+}
+
+/// Like [`get_named_paths`], but divide the work among up to `jobs` threads.
+///
+/// The calling thread enumerates folders through `kf_manager`. Each worker thread
+/// initializes COM in the multithreaded apartment and creates its own
+/// `IKnownFolderManager`, so no COM objects are shared across threads. This can be faster
+/// when some paths are slow to look up, such as those redirected to network locations.
+/// The results are in the same order as from [`get_named_paths`]. If `jobs` is 0 or 1,
+/// all the work is done on the calling thread, just as [`get_named_paths`] does it.
+pub fn get_named_paths_in_parallel(
+    kf_manager: &IKnownFolderManager,
+    options: &LookupOptions,
+    jobs: usize,
+) -> Result<Vec<NamedPath>, WindowsError> {
+    if jobs <= 1 {
+        return get_named_paths(kf_manager, options);
+    }
+
+    let ids = KnownFolderIds::new(kf_manager)?.to_unique_vec();
+    let chunk_size = ids.len().div_ceil(jobs).max(1);
+    log::info!("Looking up {} known folders on {jobs} threads", ids.len());
+
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = ids
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let _com = ComInit::new_mta()?;
+                    let kf_manager = create_known_folder_manager()?;
+                    get_named_paths_of(&kf_manager, chunk, options)
+                })
+            })
+            .collect();
+
+        let mut named_paths = Vec::with_capacity(ids.len());
+        for worker in workers {
+            let chunk_results = worker.join().expect("Bug: Worker thread panicked");
+            named_paths.extend(chunk_results?);
+        }
+        Ok(named_paths)
+    })
+}
+
+/// Iterator over known folders that keeps COM initialized for as long as it exists.
+///
+/// Fields are dropped in declaration order, so `folders`, which holds the
+/// `IKnownFolderManager`, is dropped before COM is uninitialized by `_com`.
+struct KnownFolders<I> {
+    folders: I,
+    _com: Option<ComInit>,
+}
+
+impl<I: Iterator> Iterator for KnownFolders<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.folders.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.folders.size_hint()
+    }
+}
+
+/// Lazily look up all known folders, yielding each one's name and either path or error.
+///
+/// This is like [`iter_named_paths`], but initializes COM and creates an
+/// `IKnownFolderManager` itself. The iterator keeps COM initialized on the calling thread,
+/// and keeps its `IKnownFolderManager` alive, for as long as it exists, so it must be used
+/// on the thread that called this. Folders are looked up as `options` say. If the folders
+/// could not be enumerated at all, the iterator yields only that error.
+pub fn known_folders(
+    options: &LookupOptions,
+) -> impl Iterator<Item = Result<NamedPath, WindowsError>> {
+    let options = *options;
+    let result = (|| {
+        // If the thread is already in the MTA, COM is initialized and will stay so.
+        let com = match ComInit::new() {
+            Ok(com) => Some(com),
+            Err(e) if e.code() == RPC_E_CHANGED_MODE => None,
+            Err(e) => return Err(e),
+        };
+        let kf_manager = create_known_folder_manager()?;
+        let folders = iter_named_paths(kf_manager, options)?;
+        Ok(KnownFolders { folders, _com: com })
+    })();
+
+    let (error, folders) = match result {
+        Ok(folders) => (None, Some(folders)),
+        Err(e) => (Some(Err(e)), None),
+    };
+    error.into_iter().chain(folders.into_iter().flatten())
+}
+
+/// Get the IDs of all known folders, without getting any other information about them.
+///
+/// Each ID is included once, in the order the system first lists it.
+pub fn get_known_folder_ids(kf_manager: &IKnownFolderManager) -> Result<Vec<GUID>, WindowsError> {
+    Ok(KnownFolderIds::new(kf_manager)?.to_unique_vec())
+}
+
+/// Get one known folder, by its canonical name, and either its path or an error.
+///
+/// This looks the folder up directly, rather than enumerating all known folders. An
+/// error is returned if there is no known folder of that name.
+pub fn get_named_path_by_name(
+    kf_manager: &IKnownFolderManager,
+    name: &str,
+    options: &LookupOptions,
+) -> Result<NamedPath, WindowsError> {
+    KnownFolder::by_name(kf_manager, name)?.to_named_path(options)
+}
+
+/// Get one known folder, by its ID, and either its path or an error.
+///
+/// This looks the folder up directly, rather than enumerating all known folders. An
+/// error is returned if there is no known folder with that ID.
+pub fn get_named_path_by_id(
+    kf_manager: &IKnownFolderManager,
+    id: &GUID,
+    options: &LookupOptions,
+) -> Result<NamedPath, WindowsError> {
+    KnownFolder::by_id(kf_manager, id)?.to_named_path(options)
+}
+
+/// A known folder, whose information is only retrieved as it is asked for.
+///
+/// Unlike a [`NamedPath`], this holds the folder's `IKnownFolder`, so callers can get just
+/// the information they need. It is gotten through an `IKnownFolderManager`, such as from
+/// [`create_known_folder_manager`]. It must be used on the thread that got it, and COM must
+/// stay initialized on that thread, such as by holding a [`ComInit`], for as long as it
+/// exists.
+pub struct KnownFolder {
+    id: GUID,
+    folder: IKnownFolder,
+}
+
+impl KnownFolder {
+    /// Get the known folder with the given ID.
+    pub fn by_id(kf_manager: &IKnownFolderManager, id: &GUID) -> Result<Self, WindowsError> {
+        let folder = unsafe { kf_manager.GetFolder(id)? };
+        Ok(Self { id: *id, folder })
+    }
+
+    /// Get the known folder with the given canonical name.
+    pub fn by_name(kf_manager: &IKnownFolderManager, name: &str) -> Result<Self, WindowsError> {
+        unsafe {
+            let folder = kf_manager.GetFolderByName(&HSTRING::from(name))?;
+            let id = folder.GetId()?;
+            Ok(Self { id, folder })
+        }
+    }
+
+    /// Get all known folders, without retrieving any information about them but their IDs.
+    ///
+    /// If one folder can't be gotten, the others are still returned, along with its error.
+    pub fn all(
+        kf_manager: &IKnownFolderManager,
+    ) -> Result<Vec<Result<Self, WindowsError>>, WindowsError> {
+        unsafe {
+            let ids = KnownFolderIds::new(kf_manager)?.to_unique_vec();
+            let folders = ids
+                .iter()
+                .map(|id| {
+                    let folder = kf_manager.GetFolder(id)?;
+                    Ok(Self { id: *id, folder })
+                })
+                .collect();
+            Ok(folders)
+        }
+    }
+
+    /// The folder's ID.
+    pub fn id(&self) -> GUID {
+        self.id
+    }
+
+    /// Get the folder's full definition. This owns its strings and frees them when dropped.
+    pub fn definition(&self) -> Result<KnownFolderDefinition, WindowsError> {
+        KnownFolderDefinition::of(&self.folder)
+    }
+
+    /// Get the folder's canonical name.
+    ///
+    /// Invalid UTF-16 is replaced, with a warning logged.
+    pub fn name(&self) -> Result<String, WindowsError> {
+        let definition = self.definition()?;
+        Ok(to_string_lossy(definition.fields.pszName, &self.id, "name"))
+    }
+
+    /// Get the folder's localized display name, if it has one that can be loaded.
+    ///
+    /// Invalid UTF-16 is replaced, with a warning logged.
+    pub fn localized_name(&self) -> Result<Option<String>, WindowsError> {
+        let definition = self.definition()?;
+        Ok(match definition.fields.pszLocalizedName {
+            pwstr if pwstr.is_null() => None,
+            pwstr => load_indirect_string(pwstr, &self.id, "localized name").ok(),
+        })
+    }
+
+    /// Get the folder's path, looked up with the given flags.
+    ///
+    /// Invalid UTF-16 is replaced, with a warning logged.
+    pub fn path(&self, flags: KNOWN_FOLDER_FLAG) -> Result<String, WindowsError> {
+        try_get_path(&self.folder, &self.id, flags, "path")
+    }
+
+    /// Get the folder's category, if it is one we recognize.
+    pub fn category(&self) -> Result<Option<Category>, WindowsError> {
+        let raw = unsafe { self.folder.GetCategory()? };
+        Ok(Category::from_raw(raw))
+    }
+
+    /// Get the folder's type ID, if it has one.
+    pub fn folder_type(&self) -> Result<Option<GUID>, WindowsError> {
+        let definition = self.definition()?;
+        Ok(Some(definition.fields.ftidType).filter(|id| *id != GUID::zeroed()))
+    }
+
+    /// Get the ID of the folder's parent known folder, if it has one.
+    pub fn parent_id(&self) -> Result<Option<GUID>, WindowsError> {
+        let definition = self.definition()?;
+        Ok(Some(definition.fields.fidParent).filter(|id| *id != GUID::zeroed()))
+    }
+
+    /// Retrieve all the information about the folder, looking up its paths as the options
+    /// say, as the functions returning a [`NamedPath`] do.
+    pub fn to_named_path(&self, options: &LookupOptions) -> Result<NamedPath, WindowsError> {
+        get_named_path(self.id, &self.folder, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_paths_round_trip_through_json() {
+        let path = format!(r"C:\Users\me{}", r"\subdirectory".repeat(20));
+        assert!(path.len() > 260);
+        let json = serde_json::json!({
+            "name": "Documents",
+            "guid": "{FDD39AD0-238F-46AF-ADB4-6C85480369C7}",
+            "folderid": "FOLDERID_Documents",
+            "category": "PerUser",
+            "path": path,
+            "redirected": false,
+        });
+
+        let np: NamedPath = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(np.try_path.as_deref().ok(), Some(path.as_str()));
+        assert_eq!(serde_json::to_value(&np).unwrap(), json);
+    }
+}
@@ -2,46 +2,94 @@
 //!
 //! See [Known Folders](https://learn.microsoft.com/en-us/windows/win32/shell/known-folders).
 
-use core::ffi::c_void;
-use std::collections::HashMap;
-use std::string::FromUtf16Error;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, IsTerminal, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{self, AtomicBool};
+use std::time::{Duration, Instant};
 
+use clap::{ArgGroup, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use serde::Serialize;
 use thiserror::Error;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-use windows::core::{Error as WindowsError, GUID, PWSTR};
-use windows::Win32::System::Com::{
-    CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, CLSCTX_INPROC_SERVER,
-    COINIT_APARTMENTTHREADED,
+use windows::core::{Error as WindowsError, GUID, HRESULT, HSTRING, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{CloseHandle, BOOL, ERROR_PRIVILEGE_NOT_HELD, HANDLE};
+use windows::Win32::Globalization::{
+    GetProcessPreferredUILanguages, LCIDToLocaleName, LocaleNameToLCID,
+    SetProcessPreferredUILanguages, LOCALE_ALLOW_NEUTRAL_NAMES, MUI_LANGUAGE_NAME,
+};
+use windows::Win32::Security::{ImpersonateLoggedOnUser, RevertToSelf};
+use windows::Win32::Storage::FileSystem::{
+    GetFileAttributesW, FILE_ATTRIBUTE_REPARSE_POINT, INVALID_FILE_ATTRIBUTES,
+};
+use windows::Win32::System::Console::{
+    GetConsoleMode, GetStdHandle, SetConsoleCtrlHandler, SetConsoleMode, CONSOLE_MODE,
+    ENABLE_VIRTUAL_TERMINAL_PROCESSING, STD_OUTPUT_HANDLE,
+};
+use windows::Win32::System::Environment::ExpandEnvironmentStringsW;
+use windows::Win32::System::RemoteDesktop::{
+    WTSDomainName, WTSEnumerateSessionsW, WTSFreeMemory, WTSQuerySessionInformationW,
+    WTSQueryUserToken, WTSUserName, WTS_CURRENT_SERVER_HANDLE, WTS_INFO_CLASS,
+};
+use windows::Win32::System::SystemInformation::{
+    ComputerNameDnsHostname, GetComputerNameExW, GetLocalTime,
 };
 use windows::Win32::UI::Shell::{
-    IKnownFolder, IKnownFolderManager, KnownFolderManager, KF_FLAG_ALIAS_ONLY, KF_FLAG_CREATE,
-    KF_FLAG_DEFAULT, KF_FLAG_DEFAULT_PATH, KF_FLAG_DONT_UNEXPAND, KF_FLAG_DONT_VERIFY,
+    FOLDERID_Profile, IKnownFolderManager, KF_FLAG_ALIAS_ONLY, KF_FLAG_CREATE, KF_FLAG_DEFAULT,
+    KF_FLAG_DEFAULT_PATH, KF_FLAG_DONT_UNEXPAND, KF_FLAG_DONT_VERIFY,
     KF_FLAG_FORCE_APPCONTAINER_REDIRECTION, KF_FLAG_FORCE_APP_DATA_REDIRECTION,
     KF_FLAG_FORCE_PACKAGE_REDIRECTION, KF_FLAG_INIT, KF_FLAG_NOT_PARENT_RELATIVE, KF_FLAG_NO_ALIAS,
     KF_FLAG_NO_PACKAGE_REDIRECTION, KF_FLAG_RETURN_FILTER_REDIRECTION_TARGET,
-    KF_FLAG_SIMPLE_IDLIST, KNOWNFOLDER_DEFINITION, KNOWN_FOLDER_FLAG,
+    KF_FLAG_SIMPLE_IDLIST, KF_REDIRECTION_CAPABILITIES_DENY_PERMISSIONS,
+    KF_REDIRECTION_CAPABILITIES_DENY_POLICY, KF_REDIRECTION_CAPABILITIES_DENY_POLICY_REDIRECTED,
+    KF_REDIRECTION_CAPABILITIES_REDIRECTABLE, KNOWN_FOLDER_FLAG,
 };
 
-/// An error converting a command-line argument to a `KNOWN_FOLDER_FLAG` value.
+use knfo::{
+    create_known_folder_manager, describe_error, folder_type_id_name, get_known_folder_ids,
+    get_named_path_by_id, get_named_path_by_name, get_named_paths_in_parallel, guid_from_string,
+    guid_to_string, hresult_hex, iter_named_paths, named, Category, ComInit, KnownFolder,
+    LookupOptions, NamedPath,
+};
+
+/// An error parsing command-line arguments.
 #[derive(Debug, Error)]
-enum FlagError {
-    #[error("No options are recognized (got {0:?})")]
-    AttemptedOption(String),
+enum ArgError {
+    #[error("Can't parse raw flags as a decimal or 0x-prefixed hex integer: {0}")]
+    InvalidRawFlags(String),
+
+    #[error(
+        "Can't parse GUID, which should look like {{01234567-89AB-CDEF-0123-456789ABCDEF}}: {0}"
+    )]
+    InvalidGuid(String),
+
+    #[error("Unrecognized category (expected virtual, fixed, common, or peruser): {0}")]
+    UnrecognizedCategory(String),
 
-    #[error("Unrecognized flag name: {0}")]
-    UnrecognizedFlag(String),
+    #[error("Unrecognized locale (expected a name like fr-FR, or an LCID like 0x040C): {0}")]
+    UnrecognizedLocale(String),
+
+    #[error("Unrecognized flag name: {0} (normalized {1}){}", did_you_mean(.2))]
+    UnrecognizedFlag(String, String, Option<&'static str>),
 
     #[error("Refusing to attempt to pass {0} for ALL known folders (dangerous)")]
     BannedFlag(String),
+
+    #[error("Flags {0} and {1} contradict each other, so they can't both be passed")]
+    ContradictoryFlags(&'static str, &'static str),
 }
 
-/// Makes an array of pairs of each name as a string with the resolved name.
-macro_rules! named {
-    ($($ident:ident),* $(,)?) => {
-        [$(
-            (stringify!($ident), $ident),
-        )*]
-    };
+/// Format a suggested flag name to append to an error message, if there is one.
+fn did_you_mean(suggestion: &Option<&str>) -> String {
+    match suggestion {
+        Some(name) => format!("; did you mean {name}?"),
+        None => String::new(),
+    }
 }
 
 /// Pairs of known folder flags' symbolic names and the flag values.
@@ -52,7 +100,7 @@ const NAMED_KF_FLAGS: &[(&str, KNOWN_FOLDER_FLAG)] = &named!(
     KF_FLAG_FORCE_PACKAGE_REDIRECTION,
     KF_FLAG_NO_PACKAGE_REDIRECTION,
     KF_FLAG_FORCE_APPCONTAINER_REDIRECTION,
-    KF_FLAG_CREATE, // Though we will refuse to attempt it.
+    KF_FLAG_CREATE, // Though we refuse to attempt it, unless --i-understand-create is passed.
     KF_FLAG_DONT_VERIFY,
     KF_FLAG_DONT_UNEXPAND,
     KF_FLAG_NO_ALIAS,
@@ -63,228 +111,3061 @@ const NAMED_KF_FLAGS: &[(&str, KNOWN_FOLDER_FLAG)] = &named!(
     KF_FLAG_ALIAS_ONLY,
 );
 
+/// Pairs of known folder flags and brief descriptions of their effects, for `--explain`.
+const KF_FLAG_DESCRIPTIONS: &[(KNOWN_FOLDER_FLAG, &str)] = &[
+    (KF_FLAG_DEFAULT, "No flags: get each folder's usual path."),
+    (
+        KF_FLAG_FORCE_APP_DATA_REDIRECTION,
+        "In a packaged app, redirect AppData folders to the package's own AppData.",
+    ),
+    (
+        KF_FLAG_RETURN_FILTER_REDIRECTION_TARGET,
+        "Return the target of a folder redirected by a filter, rather than its usual path.",
+    ),
+    (
+        KF_FLAG_FORCE_PACKAGE_REDIRECTION,
+        "Return the path with package redirection, even outside a packaged app.",
+    ),
+    (
+        KF_FLAG_NO_PACKAGE_REDIRECTION,
+        "Return the path without package redirection, even in a packaged app.",
+    ),
+    (
+        KF_FLAG_FORCE_APPCONTAINER_REDIRECTION,
+        "Return the path with app container redirection, even outside an app container.",
+    ),
+    (
+        KF_FLAG_CREATE,
+        "Create the folder if it doesn't exist, for ALL known folders. (Dangerous!)",
+    ),
+    (
+        KF_FLAG_DONT_VERIFY,
+        "Return the path even if the folder doesn't exist, without checking.",
+    ),
+    (
+        KF_FLAG_DONT_UNEXPAND,
+        "Store paths without environment variables. Only affects setting paths, not getting.",
+    ),
+    (
+        KF_FLAG_NO_ALIAS,
+        "Return the folder's actual path, rather than an alias for it.",
+    ),
+    (
+        KF_FLAG_INIT,
+        "Initialize a created folder's desktop.ini. (Only meaningful with KF_FLAG_CREATE.)",
+    ),
+    (
+        KF_FLAG_DEFAULT_PATH,
+        "Return the default path, as if the folder were not redirected.",
+    ),
+    (
+        KF_FLAG_NOT_PARENT_RELATIVE,
+        "With KF_FLAG_DEFAULT_PATH, don't make the path relative to the parent's current path.",
+    ),
+    (
+        KF_FLAG_SIMPLE_IDLIST,
+        "Make a simple ID list. Only affects ID lists, not paths.",
+    ),
+    (
+        KF_FLAG_ALIAS_ONLY,
+        "Return only an alias path for the folder, failing if it has none.",
+    ),
+];
+
+/// Pairs of short names of `KF_REDIRECTION_CAPABILITIES` bits and the bits.
+///
+/// `KF_REDIRECTION_CAPABILITIES_ALLOW_ALL` and `KF_REDIRECTION_CAPABILITIES_DENY_ALL` are
+/// not here, because they are masks of all the bits that allow and deny redirection.
+const NAMED_REDIRECTION_CAPABILITIES: &[(&str, u32)] = &[
+    (
+        "REDIRECTABLE",
+        KF_REDIRECTION_CAPABILITIES_REDIRECTABLE.0 as u32,
+    ),
+    (
+        "DENY_POLICY_REDIRECTED",
+        KF_REDIRECTION_CAPABILITIES_DENY_POLICY_REDIRECTED.0 as u32,
+    ),
+    (
+        "DENY_POLICY",
+        KF_REDIRECTION_CAPABILITIES_DENY_POLICY.0 as u32,
+    ),
+    (
+        "DENY_PERMISSIONS",
+        KF_REDIRECTION_CAPABILITIES_DENY_PERMISSIONS.0 as u32,
+    ),
+];
+
 /// Flags we refuse to pass, because we would be passing them for ALL known folders.
 ///
-/// See `read_args_as_kf_flags` for details.
+/// See `read_args` for details.
 const BANNED_KF_FLAGS: &[KNOWN_FOLDER_FLAG] = &[KF_FLAG_CREATE, KF_FLAG_INIT];
 
+/// Pairs of flags that contradict each other, so that passing both is surely a mistake.
+const INCOMPATIBLE_KF_FLAGS: &[(KNOWN_FOLDER_FLAG, KNOWN_FOLDER_FLAG)] = &[
+    (KF_FLAG_NO_ALIAS, KF_FLAG_ALIAS_ONLY),
+    (
+        KF_FLAG_FORCE_PACKAGE_REDIRECTION,
+        KF_FLAG_NO_PACKAGE_REDIRECTION,
+    ),
+];
+
+/// Get the symbolic name of a flag that is in `NAMED_KF_FLAGS`.
+fn flag_name(flag: KNOWN_FOLDER_FLAG) -> &'static str {
+    NAMED_KF_FLAGS
+        .iter()
+        .find(|(_, named_flag)| *named_flag == flag)
+        .map(|(name, _)| *name)
+        .expect("Bug: Flag is somehow missing from the table of named flags")
+}
+
+/// Describe the effect of each of the given flags, one per line, for `--explain`.
+///
+/// Bits that are not those of any named flag, which can be passed with `--raw-flags`,
+/// are shown in hex and described as unknown.
+fn explain_flags(flags: KNOWN_FOLDER_FLAG) -> String {
+    let mut text = String::from("Flags:");
+    let mut unknown_bits = flags.0;
+    for &(flag, description) in KF_FLAG_DESCRIPTIONS {
+        // Since KF_FLAG_DEFAULT is zero, all flags contain it, so it is only shown alone.
+        let shown = if flag == KF_FLAG_DEFAULT {
+            flags == KF_FLAG_DEFAULT
+        } else {
+            flags.contains(flag)
+        };
+        if shown {
+            text.push_str(&format!("\n  {}: {description}", flag_name(flag)));
+            unknown_bits &= !flag.0;
+        }
+    }
+    if unknown_bits != 0 {
+        text.push_str(&format!(
+            "\n  0x{unknown_bits:08X}: Unknown flags, passed as given."
+        ));
+    }
+    text
+}
+
+/// Describe the exact flags value, such as `flags=0x00004000 (KF_FLAG_DONT_VERIFY)`.
+///
+/// The set bits are decoded into the names of the flags they are, joined with `|`, with
+/// any bits that are not those of a named flag shown together in hex.
+fn describe_flags(flags: KNOWN_FOLDER_FLAG) -> String {
+    let mut names = vec![];
+    let mut unknown_bits = flags.0;
+    for &(name, flag) in NAMED_KF_FLAGS {
+        if flag != KF_FLAG_DEFAULT && flags.contains(flag) {
+            names.push(name.to_owned());
+            unknown_bits &= !flag.0;
+        }
+    }
+    if unknown_bits != 0 {
+        names.push(format!("0x{unknown_bits:08X}"));
+    }
+    if names.is_empty() {
+        names.push(flag_name(KF_FLAG_DEFAULT).to_owned());
+    }
+    format!("flags=0x{:08X} ({})", flags.0, names.join(" | "))
+}
+
+/// Refuse combined flags that include both flags of any pair in `INCOMPATIBLE_KF_FLAGS`.
+fn check_compatible(flags: KNOWN_FOLDER_FLAG) -> Result<(), ArgError> {
+    for &(a, b) in INCOMPATIBLE_KF_FLAGS {
+        if flags.contains(a) && flags.contains(b) {
+            return Err(ArgError::ContradictoryFlags(flag_name(a), flag_name(b)));
+        }
+    }
+    Ok(())
+}
+
 /// Convert an informal representation of a `KNOWN_FOLDER_FLAG` to the real name.
+///
+/// Case is ignored, hyphens may be used in place of underscores, and the name may be
+/// given with all, part, or none of its `KF_FLAG_` prefix: as `KF_FLAG_`, `FLAG_`, `KF_`,
+/// or nothing at all.
 fn normalize_flag_name(flag_arg: &str) -> String {
     const PREFIX: &str = "KF_FLAG_";
-    let upcased = flag_arg.to_uppercase();
-    if upcased.starts_with(PREFIX) {
-        upcased
-    } else {
-        format!("{PREFIX}{upcased}")
+    let normalized = flag_arg.to_uppercase().replace('-', "_");
+    let suffix = ["KF_FLAG_", "FLAG_", "KF_"]
+        .into_iter()
+        .find_map(|prefix| normalized.strip_prefix(prefix))
+        .unwrap_or(&normalized);
+    format!("{PREFIX}{suffix}")
+}
+
+/// Refuse flags with any of the same bits as a banned flag. See `read_args`.
+fn check_not_banned(flags: KNOWN_FOLDER_FLAG) -> Result<(), ArgError> {
+    for &banned_flag in BANNED_KF_FLAGS {
+        if flags.0 & banned_flag.0 != 0 {
+            return Err(ArgError::BannedFlag(flag_name(banned_flag).to_owned()));
+        }
     }
+    Ok(())
 }
 
-/// Parse command line arguments as `KNOWN_FOLDER_FLAG` values.
+/// Parse a raw `KNOWN_FOLDER_FLAG` value, given as a decimal or `0x`-prefixed hex integer.
 ///
-/// Note that these represent how the operation of looking up a known folder's path
-/// is customized. They do not identify specific known folders. (This program always
-/// displays information about all registered known folders.)
+/// This does not refuse banned flags. `read_args` does that, once all flags are combined.
+fn parse_raw_flags(text: &str) -> Result<KNOWN_FOLDER_FLAG, ArgError> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    let bits = parsed.map_err(|_| ArgError::InvalidRawFlags(text.to_owned()))?;
+    Ok(KNOWN_FOLDER_FLAG(bits as i32))
+}
+
+/// Parse a GUID in its usual hyphenated form, with or without enclosing braces.
+fn parse_guid(text: &str) -> Result<GUID, ArgError> {
+    guid_from_string(text).ok_or_else(|| ArgError::InvalidGuid(text.to_owned()))
+}
+
+/// Parse a known folder category name, ignoring case.
+fn parse_category(text: &str) -> Result<Category, ArgError> {
+    [
+        Category::Virtual,
+        Category::Fixed,
+        Category::Common,
+        Category::PerUser,
+    ]
+    .into_iter()
+    .find(|category| category.name().eq_ignore_ascii_case(text))
+    .ok_or_else(|| ArgError::UnrecognizedCategory(text.to_owned()))
+}
+
+/// Parse a locale name, such as `fr-FR`, or a decimal or `0x`-prefixed hex LCID.
 ///
-/// This refuses to accept flags that would attempt to create directories for all
-/// registered known folders that do not yet have them, or that would only be
-/// meaningful in the presence of other flags that do this, since using this
-/// diagnostic utility to create a potentially large number of directories is very
-/// unlikely to be intended. To just see what the paths *would* all be if they were
-/// created, the `KF_FLAG_DONT_VERIFY` flag can be used.
-fn read_args_as_kf_flags() -> Result<KNOWN_FOLDER_FLAG, FlagError> {
-    let table: HashMap<_, _> = HashMap::from_iter(NAMED_KF_FLAGS.iter().cloned());
-    let mut flags = KF_FLAG_DEFAULT;
-    assert_eq!(flags.0, 0, "Bug: Default flags are somehow nonzero!");
+/// Either way, this gives the locale name, which it checks is one Windows recognizes.
+fn parse_locale(text: &str) -> Result<String, ArgError> {
+    // Enough for any locale name, including its null terminator. See LOCALE_NAME_MAX_LENGTH.
+    const MAX_LENGTH: usize = 85;
 
-    for flag_arg in std::env::args().skip(1) {
-        if flag_arg.starts_with('-') {
-            return Err(FlagError::AttemptedOption(flag_arg));
-        }
+    let unrecognized = || ArgError::UnrecognizedLocale(text.to_owned());
+    let lcid = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    };
 
-        let flag_name = normalize_flag_name(&flag_arg);
-        match table.get(flag_name.as_str()) {
-            None => return Err(FlagError::UnrecognizedFlag(flag_name)),
-            Some(flag) if BANNED_KF_FLAGS.contains(flag) => {
-                return Err(FlagError::BannedFlag(flag_name));
+    match lcid {
+        Some(lcid) => {
+            let mut buffer = [0u16; MAX_LENGTH];
+            let len =
+                unsafe { LCIDToLocaleName(lcid, Some(&mut buffer), LOCALE_ALLOW_NEUTRAL_NAMES) };
+            if len <= 1 {
+                return Err(unrecognized());
             }
-            Some(flag) => flags |= *flag,
+            Ok(String::from_utf16_lossy(&buffer[..(len - 1) as usize]))
+        }
+        None => {
+            let lcid =
+                unsafe { LocaleNameToLCID(&HSTRING::from(text), LOCALE_ALLOW_NEUTRAL_NAMES) };
+            if lcid == 0 {
+                return Err(unrecognized());
+            }
+            Ok(text.to_owned())
         }
     }
+}
 
-    for banned_flag in BANNED_KF_FLAGS {
-        assert!(
-            !flags.contains(*banned_flag),
-            "Bug: Other flags somehow combined to form banned flag {banned_flag:?}"
-        );
+/// Exit code for when a specifically requested known folder is not found.
+const NOT_FOUND: u8 = 3;
+
+/// Exit code for when COM, or the known folder manager, is unavailable.
+const NO_SHELL: u8 = 4;
+
+/// Report that the environment lacks what we need to use known folders at all.
+fn report_no_shell(what: &str, error: &WindowsError) -> ExitCode {
+    eprintln!(
+        "Error: {what} failed; this tool requires a Windows shell environment: {}",
+        describe_error(error),
+    );
+    ExitCode::from(NO_SHELL)
+}
+
+/// Build the list of flag names shown by `--help`, noting which we refuse to pass.
+fn flag_names_help() -> String {
+    let mut text = String::from("Flag names:");
+    for (name, flag) in NAMED_KF_FLAGS {
+        text.push_str("\n  ");
+        text.push_str(name);
+        if BANNED_KF_FLAGS.contains(flag) {
+            text.push_str(" (refused without --i-understand-create)");
+        }
     }
+    text.push_str(
+        "\n\nCase is ignored, and hyphens may be used in place of underscores. The KF_FLAG_\n\
+         prefix may be given in full, shortened to FLAG_ or KF_, or omitted, so that\n\
+         KF_FLAG_DONT_VERIFY, flag-dont-verify, kf_dont_verify, and dont-verify all mean\n\
+         the same flag.",
+    );
+    text.push_str(&format!(
+        "\n\nMore flag names, separated by whitespace, may be given in the {FLAGS_VAR} variable."
+    ));
+    text
+}
 
-    Ok(flags)
+/// How the results should be displayed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// An aligned text table of names and paths.
+    #[default]
+    Table,
+
+    /// A JSON array of objects, one per known folder, indented.
+    Json,
+
+    /// The same JSON as `json`, but on one line, with no whitespace.
+    JsonCompact,
+
+    /// Newline-delimited JSON: one compact JSON object per known folder, on its own line.
+    Ndjson,
+
+    /// Comma-separated values with a header row.
+    Csv,
+
+    /// Tab-separated values with no header row.
+    Tsv,
+
+    /// An XML document with a `folder` element per known folder.
+    Xml,
+
+    /// A GitHub-flavored Markdown table of names and paths.
+    Markdown,
+
+    /// Variable assignments, such as `KF_Downloads=C:\Users\me\Downloads`, one per line.
+    Env,
+
+    /// A block of `key=value` lines per known folder, with all fields, separated by blank
+    /// lines.
+    Kv,
+
+    /// A self-contained HTML document with a table of names, categories, and paths.
+    Html,
 }
 
-/// Guard type that initializes COM on the current thread and uninitializes it on drop.
-struct ComInit;
+/// What to sort the results by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum SortKey {
+    /// Sort by name.
+    #[default]
+    Name,
 
-impl ComInit {
-    fn new() -> Result<Self, WindowsError> {
-        unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED) }.ok()?;
-        Ok(Self)
-    }
+    /// Sort by path, placing folders whose paths could not be obtained last.
+    Path,
+
+    /// Sort by GUID, in the registry form, as registry tools usually list them.
+    Guid,
+
+    /// Don't sort. Show folders in the order the system enumerates them.
+    ///
+    /// This is the order `GetFolderIds` returns their IDs in, even when `--jobs` is used.
+    None,
 }
 
-impl Drop for ComInit {
-    fn drop(&mut self) {
-        unsafe { CoUninitialize() };
-    }
+/// Whether to color the table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum ColorChoice {
+    /// Color the table if standard output is a terminal that we can make support color.
+    #[default]
+    Auto,
+
+    /// Color the table, even if standard output is not a terminal.
+    Always,
+
+    /// Don't color the table.
+    Never,
 }
 
-/// Free a `PWSTR` with `CoTaskMemFree`.
-fn co_free_pwstr(pwstr: PWSTR) {
-    unsafe { CoTaskMemFree(Some(pwstr.as_ptr().cast::<c_void>())) };
+/// How to encode text written to an output file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum Encoding {
+    /// UTF-8, with no byte order mark.
+    #[default]
+    Utf8,
+
+    /// UTF-16LE, starting with a byte order mark, as some older Windows tools expect.
+    Utf16le,
+}
+
+/// How much to log to standard error about what the program is doing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum LogLevel {
+    /// Don't log.
+    Off,
+
+    /// Log only errors, such as a folder that can't be retrieved at all with --fail-fast.
+    Error,
+
+    /// Also log warnings, such as strings with invalid UTF-16.
+    #[default]
+    Warn,
+
+    /// Also log folders and paths that can't be gotten, retries, and how many are looked up.
+    Info,
+
+    /// Also log each folder looked up, how long its path took, and COM initialization.
+    Debug,
 }
 
-/// Owner of a `PWSTR` that must be freed with `CoTaskMemFree`.
-struct CoStr {
-    pwstr: PWSTR,
+impl LogLevel {
+    fn to_filter(self) -> log::LevelFilter {
+        match self {
+            Self::Off => log::LevelFilter::Off,
+            Self::Error => log::LevelFilter::Error,
+            Self::Warn => log::LevelFilter::Warn,
+            Self::Info => log::LevelFilter::Info,
+            Self::Debug => log::LevelFilter::Debug,
+        }
+    }
 }
 
-impl CoStr {
-    fn new(pwstr: PWSTR) -> Self {
-        Self { pwstr }
+/// Logger that writes each message to standard error as a single line.
+///
+/// Each line is written with one call, while standard error is locked, so that lines
+/// logged from different threads are not interleaved.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
     }
 
-    fn to_string(&self) -> Result<String, FromUtf16Error> {
-        unsafe { self.pwstr.to_string() }
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
     }
+
+    fn flush(&self) {}
 }
 
-impl Drop for CoStr {
-    fn drop(&mut self) {
-        co_free_pwstr(self.pwstr);
-    }
+/// How to group folders in the table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum GroupBy {
+    /// Group folders by category, under a heading for each.
+    Category,
+}
+
+/// A column the table can show.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Column {
+    /// The name, indented in tree mode.
+    Name,
+
+    /// The localized display name, if any.
+    LocalizedName,
+
+    /// The category.
+    Category,
+
+    /// The GUID.
+    Guid,
+
+    /// The name of the `FOLDERID_*` constant.
+    Folderid,
+
+    /// The shell parsing name.
+    ParsingName,
+
+    /// The path relative to the parent known folder.
+    RelativePath,
+
+    /// The icon resource string.
+    Icon,
+
+    /// The security descriptor, in SDDL.
+    Security,
+
+    /// The folder type, as a `FOLDERTYPEID_*` name if it has one, or otherwise a GUID.
+    Type,
+
+    /// Whether the path is a reparse point.
+    Reparse,
+
+    /// Whether, and why not, the folder can be redirected.
+    RedirectCaps,
+
+    /// The size and bytes of the item ID list (PIDL).
+    Idlist,
+
+    /// How long the path took to look up.
+    Timing,
+
+    /// The path, or why it is unavailable, with any notes.
+    Path,
 }
 
-/// Owner of `IKnownFolderManager::GetFolderIds` results.
+/// List Windows known folders and their paths.
 ///
-/// On drop, this calls `CoTaskMemFree` on the block of GUIDs representing known folders.
-struct KnownFolderIds {
-    pkfid: *mut GUID,
-    count: u32,
+/// Each argument that is not an option is the name of a KNOWN_FOLDER_FLAG to pass when
+/// looking up every folder's path. The KF_FLAG_ prefix may be omitted, and case does not
+/// matter, so KF_FLAG_DONT_VERIFY may be given as dont_verify.
+#[derive(Debug, Parser)]
+#[command(version, group(ArgGroup::new("single").args(["name", "guid"])))]
+struct Options {
+    /// Flags to pass, combined from the flag names and the raw flags given.
+    #[arg(skip)]
+    flags: KNOWN_FOLDER_FLAG,
+
+    /// Names of flags to pass, with or without the KF_FLAG_ prefix, ignoring case
+    #[arg(value_name = "FLAGS")]
+    flag_names: Vec<String>,
+
+    /// Also pass flags given as a decimal or 0x-prefixed hex integer
+    #[arg(long, value_name = "N", value_parser = parse_raw_flags)]
+    raw_flags: Vec<KNOWN_FOLDER_FLAG>,
+
+    /// Show paths even if they don't exist; same as passing KF_FLAG_DONT_VERIFY
+    #[arg(long, visible_alias = "dont-verify")]
+    no_verify: bool,
+
+    /// Show redirection targets; same as passing KF_FLAG_RETURN_FILTER_REDIRECTION_TARGET
+    #[arg(long)]
+    redirect_target: bool,
+
+    /// Accept KF_FLAG_CREATE and KF_FLAG_INIT, creating missing folders (dangerous!)
+    #[arg(long)]
+    i_understand_create: bool,
+
+    /// Describe what each flag being passed does, on standard error, before proceeding
+    #[arg(long)]
+    explain: bool,
+
+    /// Show the exact flags value being used, and its flags' names, on standard error
+    #[arg(long)]
+    show_flags: bool,
+
+    /// Output format
+    #[arg(long, value_enum, ignore_case = true, default_value_t)]
+    format: Format,
+
+    /// Write each name and path (or error) followed by a null character, for scripts
+    #[arg(short = '0', long, conflicts_with = "format")]
+    null_sep: bool,
+
+    /// Print just the path of each folder that has one, one per line, without duplicates
+    #[arg(long, conflicts_with_all = ["format", "null_sep", "single"])]
+    paths_only: bool,
+
+    /// Show exactly these table columns, in this order, separated by commas
+    #[arg(
+        long,
+        value_name = "COLUMNS",
+        value_enum,
+        ignore_case = true,
+        value_delimiter = ','
+    )]
+    columns: Vec<Column>,
+
+    /// Show only folders whose names contain TEXT, ignoring case
+    #[arg(long, value_name = "TEXT")]
+    filter: Option<String>,
+
+    /// Sort by name (default), path, or GUID, or use none to not sort
+    #[arg(long, value_name = "KEY", value_enum, ignore_case = true)]
+    sort: Option<SortKey>,
+
+    /// Don't sort; same as --sort none
+    #[arg(long, conflicts_with = "sort")]
+    no_sort: bool,
+
+    /// Show only the first N folders, after filtering and sorting
+    #[arg(long, value_name = "N")]
+    first: Option<usize>,
+
+    /// Show only folders in this category: virtual, fixed, common, or peruser
+    #[arg(long, value_name = "CATEGORY", value_parser = parse_category)]
+    category: Option<Category>,
+
+    /// Show only folders whose paths could not be obtained
+    #[arg(long)]
+    only_errors: bool,
+
+    /// Show only folders whose paths could not be obtained, but whose default paths could
+    #[arg(long, conflicts_with = "quiet")]
+    unrealized: bool,
+
+    /// Don't show folders whose paths could not be obtained
+    #[arg(long, conflicts_with = "only_errors")]
+    quiet: bool,
+
+    /// Show only folders whose paths exist as directories
+    #[arg(long)]
+    exists_only: bool,
+
+    /// Mark per-user folders whose paths are outside the user profile, and warn about them
+    #[arg(long)]
+    audit_profile: bool,
+
+    /// Note which other folders have the same path as each folder
+    #[arg(long)]
+    show_aliases: bool,
+
+    /// Show only the folder with this canonical name
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Show only the folder with this GUID, with or without braces
+    #[arg(long, value_name = "GUID", value_parser = parse_guid, conflicts_with = "name")]
+    guid: Option<GUID>,
+
+    /// Print only the path of the folder given by --name or --guid
+    #[arg(long, requires = "single")]
+    path_only: bool,
+
+    /// Show only the folders with the canonical names listed, one per line, in PATH
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["name", "guid"])]
+    names_file: Option<PathBuf>,
+
+    /// Show localized display names in place of names
+    #[arg(long)]
+    localized: bool,
+
+    /// Load localized strings in this language, given as a name like fr-FR or an LCID
+    #[arg(long, value_name = "LOCALE", value_parser = parse_locale)]
+    locale: Option<String>,
+
+    /// Add a column with each folder's localized display name, after its name
+    #[arg(long, conflicts_with = "localized")]
+    show_both_names: bool,
+
+    /// Show only folders whose path is PATH
+    #[arg(long, value_name = "PATH")]
+    which: Option<String>,
+
+    /// Add a column with each folder's GUID
+    #[arg(long)]
+    show_guid: bool,
+
+    /// Add a column with each folder's FOLDERID_* constant name
+    #[arg(long)]
+    show_folderid: bool,
+
+    /// Add a column with each folder's shell parsing name
+    #[arg(long)]
+    show_parsing_name: bool,
+
+    /// Add a column with each folder's path relative to its parent
+    #[arg(long)]
+    show_relative: bool,
+
+    /// Add a column with each folder's icon resource string
+    #[arg(long)]
+    show_icon: bool,
+
+    /// Expand environment variables in icon strings and default paths
+    #[arg(long)]
+    expand_env: bool,
+
+    /// Add a column with each folder's security descriptor, in SDDL
+    #[arg(long)]
+    show_security: bool,
+
+    /// Add a column with each folder's type, such as FOLDERTYPEID_Documents
+    #[arg(long)]
+    show_type: bool,
+
+    /// Add a column saying whether each folder's path is a reparse point
+    #[arg(long)]
+    show_reparse: bool,
+
+    /// Add a column saying whether, and why not, each folder can be redirected
+    #[arg(long)]
+    show_redirect_caps: bool,
+
+    /// Add a column with the size, and bytes in hex, of each folder's item ID list (PIDL)
+    #[arg(long)]
+    show_idlist: bool,
+
+    /// Also show each folder's default path, if it differs
+    #[arg(long)]
+    show_default: bool,
+
+    /// Show folders below their parent folders in the table
+    #[arg(long)]
+    tree: bool,
+
+    /// Group folders in the table under a heading for each group
+    #[arg(long, value_enum, conflicts_with = "tree")]
+    group_by: Option<GroupBy>,
+
+    /// Show each folder's description and tooltip below it
+    #[arg(long)]
+    verbose: bool,
+
+    /// Show each folder's tooltip below it
+    #[arg(long)]
+    show_tooltip: bool,
+
+    /// Color the table
+    #[arg(
+        long,
+        value_name = "WHEN",
+        value_enum,
+        ignore_case = true,
+        default_value_t
+    )]
+    color: ColorChoice,
+
+    /// Exit with code 1 if any shown folder's path can't be obtained
+    #[arg(long)]
+    strict: bool,
+
+    /// Print only the number of known folders
+    #[arg(long)]
+    count: bool,
+
+    /// Print only the GUIDs of known folders
+    #[arg(long)]
+    list_ids: bool,
+
+    /// Read GUIDs from standard input, one per line, and print each with its name and path
+    #[arg(long, conflicts_with_all = ["count", "list_ids"])]
+    stdin_guids: bool,
+
+    /// Show only folders whose paths differ between FLAGSET and the flags otherwise given
+    #[arg(
+        long,
+        value_name = "FLAGSET",
+        value_parser = parse_flag_set,
+        conflicts_with_all = [
+            "count", "list_ids", "stdin_guids", "single", "names_file", "format", "null_sep",
+            "paths_only", "columns", "strict", "which", "first", "sort", "no_sort", "tree",
+            "group_by", "show_aliases", "audit_profile", "show_both_names", "show_guid",
+            "show_folderid", "show_parsing_name", "show_relative", "show_icon", "expand_env",
+            "show_security", "show_type", "show_reparse", "show_redirect_caps", "show_idlist",
+            "show_default", "show_tooltip", "verbose", "timings",
+        ],
+    )]
+    diff: Option<KNOWN_FOLDER_FLAG>,
+
+    /// Also save the folders shown to the file PATH as a JSON snapshot
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = [
+            "compare", "diff", "watch", "count", "list_ids", "stdin_guids", "path_only",
+        ],
+    )]
+    save: Option<PathBuf>,
+
+    /// Show how folders differ from those in a snapshot saved with --save
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["diff", "path_only"])]
+    compare: Option<PathBuf>,
+
+    /// Show the table again every SECONDS seconds, marking paths that changed, until Ctrl+C
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = [
+            "count", "list_ids", "stdin_guids", "diff", "compare", "single", "names_file",
+            "format", "null_sep", "paths_only", "columns", "save", "strict", "which", "tree",
+            "group_by", "show_aliases", "audit_profile", "show_both_names", "show_guid",
+            "show_folderid", "show_parsing_name", "show_relative", "show_icon", "expand_env",
+            "show_security", "show_type", "show_reparse", "show_redirect_caps", "show_idlist",
+            "show_default", "show_tooltip", "verbose", "timings",
+        ],
+    )]
+    watch: Option<u64>,
+
+    /// Retry path lookups that fail with transient network errors up to N times
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+
+    /// Shorten the last table column with "…" so rows fit in N terminal columns
+    #[arg(long, value_name = "N")]
+    max_width: Option<usize>,
+
+    /// Add a column with how long each folder's path took to look up, and a total
+    #[arg(long)]
+    timings: bool,
+
+    /// Look up folders as USER, who must be logged on, by impersonating them (needs SYSTEM)
+    #[arg(long, value_name = "USER", conflicts_with = "jobs")]
+    as_user: Option<String>,
+
+    /// Look up folders on N threads
+    #[arg(long, value_name = "N", default_value = "1")]
+    jobs: NonZeroUsize,
+
+    /// Stop with an error if any folder can't be retrieved at all
+    #[arg(long, overrides_with = "keep_going")]
+    fail_fast: bool,
+
+    /// Show folders that can't be retrieved at all with their errors, and go on (default)
+    #[arg(long, overrides_with = "fail_fast")]
+    keep_going: bool,
+
+    /// How much to log to standard error about what is being done, for debugging
+    #[arg(long, value_name = "LEVEL", value_enum, default_value_t)]
+    log_level: LogLevel,
+
+    /// Write output to the file PATH instead of standard output
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Encoding of the file written by --output
+    #[arg(long, value_enum, default_value_t, requires = "output")]
+    encoding: Encoding,
 }
 
-impl KnownFolderIds {
-    fn new(kf_manager: &IKnownFolderManager) -> Result<Self, WindowsError> {
-        let mut pkfid = std::ptr::null_mut();
-        let mut count = 0;
-        unsafe { kf_manager.GetFolderIds(&mut pkfid, &mut count)? };
-        Ok(Self { pkfid, count })
+impl Options {
+    /// Whether to write NDJSON as folders are looked up, rather than after all of them are.
+    ///
+    /// This is done when all folders are listed as NDJSON, unsorted, on one thread, and
+    /// nothing else needs all of them at once.
+    fn streams_ndjson(&self) -> bool {
+        self.format == Format::Ndjson
+            && self.sort == Some(SortKey::None)
+            && self.jobs.get() == 1
+            && self.name.is_none()
+            && self.guid.is_none()
+            && self.names_file.is_none()
+            && self.which.is_none()
+            && self.save.is_none()
+            && self.compare.is_none()
+            && !self.audit_profile
     }
 
-    fn as_slice(&self) -> &[GUID] {
-        unsafe { std::slice::from_raw_parts(self.pkfid, self.count as usize) }
+    /// How to look up known folders, with the given flags.
+    ///
+    /// Item ID lists and redirection capabilities are only gotten if they will be shown.
+    fn lookup(&self, flags: KNOWN_FOLDER_FLAG) -> LookupOptions {
+        let columns = self.table_columns();
+        LookupOptions {
+            flags,
+            retries: self.retries,
+            fail_fast: self.fail_fast,
+            id_list: columns.contains(&Column::Idlist),
+            redirection_capabilities: columns.contains(&Column::RedirectCaps),
+        }
     }
-}
 
-impl Drop for KnownFolderIds {
-    fn drop(&mut self) {
-        unsafe { CoTaskMemFree(Some(self.pkfid.cast::<c_void>())) };
+    /// Look up all known folders with the given flags, using the options for doing so.
+    fn get_named_paths(
+        &self,
+        kf_manager: &IKnownFolderManager,
+        flags: KNOWN_FOLDER_FLAG,
+    ) -> Result<Vec<NamedPath>, WindowsError> {
+        get_named_paths_in_parallel(kf_manager, &self.lookup(flags), self.jobs.get())
+    }
+
+    /// The columns to show in the table.
+    ///
+    /// These are the columns given with `--columns`, if any. Otherwise, they are the name,
+    /// category, and path, with other columns the `--show-*` options and `--timings` add.
+    fn table_columns(&self) -> Vec<Column> {
+        if !self.columns.is_empty() {
+            return self.columns.clone();
+        }
+
+        let mut columns = vec![Column::Name];
+        if self.show_both_names {
+            columns.push(Column::LocalizedName);
+        }
+        columns.push(Column::Category);
+        let optional_columns = [
+            (self.show_guid, Column::Guid),
+            (self.show_folderid, Column::Folderid),
+            (self.show_parsing_name, Column::ParsingName),
+            (self.show_relative, Column::RelativePath),
+            (self.show_icon, Column::Icon),
+            (self.show_security, Column::Security),
+            (self.show_type, Column::Type),
+            (self.show_reparse, Column::Reparse),
+            (self.show_redirect_caps, Column::RedirectCaps),
+            (self.show_idlist, Column::Idlist),
+            (self.timings, Column::Timing),
+        ];
+        for (shown, column) in optional_columns {
+            if shown {
+                columns.push(column);
+            }
+        }
+        columns.push(Column::Path);
+        columns
     }
 }
 
-/// Owner of a `KNOWNFOLDER_DEFINITION` that frees its dynamic strings on drop.
-struct KnownFolderDefinition {
-    fields: KNOWNFOLDER_DEFINITION,
+/// Environment variable that may hold whitespace-separated flag names to pass.
+const FLAGS_VAR: &str = "KNFO_FLAGS";
+
+/// Greatest edit distance at which a flag name is suggested in place of an unrecognized one.
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+/// Find the flag name closest to a normalized but unrecognized one, if any is close enough.
+fn suggest_flag_name(flag_name: &str) -> Option<&'static str> {
+    NAMED_KF_FLAGS
+        .iter()
+        .map(|(name, _)| (strsim::levenshtein(flag_name, name), *name))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
 }
 
-impl KnownFolderDefinition {
-    fn of(folder: &IKnownFolder) -> Result<Self, WindowsError> {
-        let mut fields = KNOWNFOLDER_DEFINITION::default();
-        unsafe { folder.GetFolderDefinition(&mut fields)? };
-        Ok(Self { fields })
+/// Look up a flag by its name or informal name.
+///
+/// This does not refuse banned flags. Callers must do that, after combining flags.
+fn parse_flag_name(
+    table: &HashMap<&str, KNOWN_FOLDER_FLAG>,
+    flag_arg: &str,
+) -> Result<KNOWN_FOLDER_FLAG, ArgError> {
+    let flag_name = normalize_flag_name(flag_arg);
+    match table.get(flag_name.as_str()) {
+        None => {
+            let suggestion = suggest_flag_name(&flag_name);
+            Err(ArgError::UnrecognizedFlag(
+                flag_arg.to_owned(),
+                flag_name,
+                suggestion,
+            ))
+        }
+        Some(flag) => Ok(*flag),
     }
 }
 
-impl Drop for KnownFolderDefinition {
-    fn drop(&mut self) {
-        // The windows crate does not provide FreeKnownFolderDefinitionFields, possibly
-        // due to it being an __inline function. This frees each of the fields that is a
-        // pointer to a string, which is equivalent to FreeKnownFolderDefinitionFields.
-        co_free_pwstr(self.fields.pszName);
-        co_free_pwstr(self.fields.pszDescription);
-        co_free_pwstr(self.fields.pszRelativePath);
-        co_free_pwstr(self.fields.pszParsingName);
-        co_free_pwstr(self.fields.pszTooltip);
-        co_free_pwstr(self.fields.pszLocalizedName);
-        co_free_pwstr(self.fields.pszIcon);
-        co_free_pwstr(self.fields.pszSecurity);
+/// Parse a comma-separated set of flag names, such as `no_alias,dont_verify`, for `--diff`.
+///
+/// Each name is accepted, or refused, just as if it were given as a separate argument,
+/// except that banned flags are always refused here, even with `--i-understand-create`.
+fn parse_flag_set(text: &str) -> Result<KNOWN_FOLDER_FLAG, ArgError> {
+    let table: HashMap<_, _> = HashMap::from_iter(NAMED_KF_FLAGS.iter().cloned());
+    let mut flags = KF_FLAG_DEFAULT;
+    for flag_arg in text
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        flags |= parse_flag_name(&table, flag_arg)?;
     }
+    check_not_banned(flags)?;
+    check_compatible(flags)?;
+    Ok(flags)
 }
 
-/// A known folder name and either its retrieved path or an error.
-struct NamedPath {
-    name: String,
-    try_path: Result<String, WindowsError>,
-}
+/// Parse command line arguments as options and `KNOWN_FOLDER_FLAG` values.
+///
+/// Options are parsed by `clap`, which exits with a usage error if they are invalid, or
+/// prints help or version information and exits if asked to. Other arguments are flags.
+///
+/// Note that flags represent how the operation of looking up a known folder's path
+/// is customized. They do not identify specific known folders. (This program shows
+/// all registered known folders, unless `--name` is used to look up just one.)
+///
+/// This refuses to accept flags that would attempt to create directories for all
+/// registered known folders that do not yet have them, or that would only be
+/// meaningful in the presence of other flags that do this, since using this
+/// diagnostic utility to create a potentially large number of directories is very
+/// unlikely to be intended. To just see what the paths *would* all be if they were
+/// created, the `KF_FLAG_DONT_VERIFY` flag can be used. Flags may also be given as
+/// integers, with `--raw-flags`, but these are likewise refused if they have any bits
+/// in common with those flags. With `--i-understand-create`, these flags are accepted,
+/// for the rare cases when they really are wanted, but a warning is shown if they are
+/// passed. Combinations of flags that contradict each other, as listed in
+/// `INCOMPATIBLE_KF_FLAGS`, are always refused.
+///
+/// Flag names are also read from the `KNFO_FLAGS` environment variable, if it is set.
+/// Since flags are combined, it does not matter which source a flag comes from, but
+/// once the command line has been parsed, an error in the environment variable is
+/// found (and reported) before one in the flag name arguments. Only flag names, and
+/// not options, are allowed there.
+fn read_args() -> Result<Options, ArgError> {
+    let matches = Options::command()
+        .after_help(flag_names_help())
+        .get_matches();
+    let mut options = Options::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let table: HashMap<_, _> = HashMap::from_iter(NAMED_KF_FLAGS.iter().cloned());
+    let mut flags = KF_FLAG_DEFAULT;
+    assert_eq!(flags.0, 0, "Bug: Default flags are somehow nonzero!");
 
-/// Get all known folder names and either paths or an error from getting the path.
-fn get_named_paths(flags: KNOWN_FOLDER_FLAG) -> Result<Vec<NamedPath>, WindowsError> {
-    let mut named_paths = vec![];
+    if let Some(env_flags) = std::env::var_os(FLAGS_VAR) {
+        for flag_arg in env_flags.to_string_lossy().split_whitespace() {
+            flags |= parse_flag_name(&table, flag_arg)?;
+        }
+    }
 
-    unsafe {
-        let kf_manager: IKnownFolderManager =
-            CoCreateInstance(&KnownFolderManager, None, CLSCTX_INPROC_SERVER)?;
-
-        for id in KnownFolderIds::new(&kf_manager)?.as_slice() {
-            let folder = kf_manager.GetFolder(id)?;
-            let name = KnownFolderDefinition::of(&folder)?
-                .fields
-                .pszName
-                .to_string()?;
-
-            let try_path = match folder.GetPath(flags.0 as u32) {
-                Ok(pwstr) => Ok(CoStr::new(pwstr).to_string()?),
-                Err(e) => Err(e),
-            };
+    for flag_arg in &options.flag_names {
+        flags |= parse_flag_name(&table, flag_arg)?;
+    }
 
-            named_paths.push(NamedPath { name, try_path });
+    for raw_flags in &options.raw_flags {
+        flags |= *raw_flags;
+    }
+
+    if options.no_verify {
+        flags |= KF_FLAG_DONT_VERIFY;
+    }
+
+    if options.redirect_target {
+        flags |= KF_FLAG_RETURN_FILTER_REDIRECTION_TARGET;
+    }
+
+    if options.i_understand_create {
+        if check_not_banned(flags).is_err() {
+            eprintln!(
+                "WARNING: Passing KF_FLAG_CREATE or KF_FLAG_INIT for ALL known folders, as \
+                 --i-understand-create allows. Missing folders may be created!"
+            );
         }
+    } else {
+        check_not_banned(flags)?;
     }
 
-    Ok(named_paths)
+    check_compatible(flags)?;
+
+    options.flags = flags;
+    if options.no_sort {
+        options.sort = Some(SortKey::None);
+    }
+    Ok(options)
 }
 
-/// Displays a table of each known folder name with its path or why the path is unavailable.
-fn print_table(named_paths: Vec<NamedPath>) {
-    let name_width_estimate = named_paths
+/// Describe `KF_REDIRECTION_CAPABILITIES` bits as their short names, separated by `|`.
+///
+/// Bits with no names are shown together in hex. No bits at all are shown as `NONE`.
+fn describe_redirection_capabilities(capabilities: u32) -> String {
+    let mut names: Vec<_> = NAMED_REDIRECTION_CAPABILITIES
         .iter()
-        .map(|np| np.name.chars().count())
-        .max()
-        .unwrap_or(0);
+        .filter(|(_, bit)| capabilities & bit != 0)
+        .map(|(name, _)| (*name).to_owned())
+        .collect();
+    let named_bits = NAMED_REDIRECTION_CAPABILITIES
+        .iter()
+        .fold(0, |bits, (_, bit)| bits | bit);
+    let unnamed_bits = capabilities & !named_bits;
+    if unnamed_bits != 0 {
+        names.push(format!("0x{unnamed_bits:X}"));
+    }
+    if names.is_empty() {
+        "NONE".to_owned()
+    } else {
+        names.join("|")
+    }
+}
+
+/// Pad text with trailing spaces to occupy the given number of terminal columns.
+///
+/// This differs from `{:<width$}` formatting, which counts `char`s rather than columns,
+/// and thus misaligns text with wide characters, such as most CJK characters.
+fn pad_to_width(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(text.width());
+    format!("{text}{}", " ".repeat(padding))
+}
+
+/// Shorten text, if needed, to occupy at most the given number of terminal columns.
+///
+/// Shortened text ends in `…`, which is counted as one column. Like `pad_to_width`, this
+/// counts columns, not `char`s.
+fn truncate_to_width(text: &str, width: usize) -> String {
+    const ELLIPSIS: char = '…';
+    if text.width() <= width {
+        return text.to_owned();
+    }
 
-    for NamedPath { name, try_path } in named_paths {
-        let path_item = try_path.unwrap_or_else(|e| format!("[{}]", e.message()));
-        println!("{name:<name_width_estimate$}  {path_item}");
+    let mut truncated = String::new();
+    let mut used = 1; // For the ellipsis.
+    for c in text.chars() {
+        used += c.width().unwrap_or(0);
+        if used > width {
+            break;
+        }
+        truncated.push(c);
     }
+    truncated.push(ELLIPSIS);
+    truncated
 }
 
-/// Use the `IKnownFolder` API to retrieve information, and print it in tabular form.
-fn run(flags: KNOWN_FOLDER_FLAG) -> Result<(), WindowsError> {
-    let mut named_paths = get_named_paths(flags)?;
-    named_paths.sort_by(|a, b| a.name.cmp(&b.name));
-    print_table(named_paths);
-    Ok(())
+/// An ANSI color to display text in.
+#[derive(Clone, Copy, Debug)]
+enum Color {
+    Cyan,
+    Red,
+    Yellow,
 }
 
-fn main() -> Result<(), WindowsError> {
-    // Parse arguments and bail out if we cannot proceed.
-    // (Returning the FlagError would show a less useful symbolic representation.)
-    let flags = read_args_as_kf_flags().unwrap_or_else(|e| {
-        eprintln!("Error: {e}");
-        std::process::exit(2);
-    });
+impl Color {
+    /// Wrap text in the escape sequences to display it in this color.
+    fn paint(self, text: &str) -> String {
+        let code = match self {
+            Self::Cyan => 36,
+            Self::Red => 31,
+            Self::Yellow => 33,
+        };
+        format!("\x1b[{code}m{text}\x1b[0m")
+    }
+}
 
-    // To use `IKnownFolder`, we must have COM initialized on this thread.
-    let _com = ComInit::new()?;
+/// A table cell's text, and the color to display it in, if any.
+struct Cell {
+    text: String,
+    color: Option<Color>,
+}
 
-    // Use those flags to access the COM API for known folders and list them out.
-    run(flags)
+impl Cell {
+    fn plain(text: String) -> Self {
+        Self { text, color: None }
+    }
+
+    fn colored(text: String, color: Color) -> Self {
+        Self {
+            text,
+            color: Some(color),
+        }
+    }
+}
+
+/// Try to make the console, if standard output is one, interpret ANSI escape sequences.
+///
+/// This is needed on older Windows consoles, which don't do so unless asked to.
+fn enable_ansi_escapes() -> Result<(), WindowsError> {
+    unsafe {
+        let handle = GetStdHandle(STD_OUTPUT_HANDLE)?;
+        let mut mode = CONSOLE_MODE::default();
+        GetConsoleMode(handle, &mut mode)?;
+        SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING)
+    }
+}
+
+/// A preference, for the whole process, for a UI language, in place of the user's.
+///
+/// This affects the language that localized strings, such as localized names, are loaded
+/// in, on all threads. On drop, the process's previous preference, if any, is restored.
+struct UiLanguageOverride {
+    /// The previously preferred languages, as a double-null-terminated list, if any.
+    previous: Option<Vec<u16>>,
+}
+
+impl UiLanguageOverride {
+    fn new(locale: &str) -> Result<Self, WindowsError> {
+        let previous = Self::preferred_languages()?;
+        let languages: Vec<_> = locale.encode_utf16().chain([0, 0]).collect();
+        unsafe {
+            SetProcessPreferredUILanguages(MUI_LANGUAGE_NAME, PCWSTR(languages.as_ptr()), None)?;
+        }
+        Ok(Self { previous })
+    }
+
+    /// Get the process's preferred UI languages, or `None` if it has none of its own.
+    fn preferred_languages() -> Result<Option<Vec<u16>>, WindowsError> {
+        let mut count = 0;
+        let mut size = 0;
+        unsafe {
+            GetProcessPreferredUILanguages(
+                MUI_LANGUAGE_NAME,
+                &mut count,
+                PWSTR::null(),
+                &mut size,
+            )?;
+        }
+        if count == 0 {
+            return Ok(None);
+        }
+
+        let mut buffer = vec![0u16; size as usize];
+        unsafe {
+            GetProcessPreferredUILanguages(
+                MUI_LANGUAGE_NAME,
+                &mut count,
+                PWSTR(buffer.as_mut_ptr()),
+                &mut size,
+            )?;
+        }
+        Ok(Some(buffer))
+    }
+}
+
+impl Drop for UiLanguageOverride {
+    fn drop(&mut self) {
+        // A null list clears the process's preference, so the user's is used again.
+        let previous = self
+            .previous
+            .as_ref()
+            .map_or(PCWSTR::null(), |languages| PCWSTR(languages.as_ptr()));
+        unsafe {
+            let _ = SetProcessPreferredUILanguages(MUI_LANGUAGE_NAME, previous, None);
+        }
+    }
+}
+
+/// An error impersonating a user for `--as-user`.
+#[derive(Debug, Error)]
+enum ImpersonationError {
+    #[error("No session has user {0} logged on")]
+    NotLoggedOn(String),
+
+    #[error("Not permitted to get the token of user {0} (this requires running as SYSTEM)")]
+    NotPermitted(String),
+
+    #[error("{}", .0.message())]
+    Windows(#[from] WindowsError),
+}
+
+/// An impersonation, by the current thread, of a user who is logged on in some session.
+///
+/// The user's token is obtained from their session, which requires `SeTcbPrivilege`, so in
+/// practice this works only when running as SYSTEM. Impersonation applies to the current
+/// thread only. On drop, the thread reverts to its own identity and the token is closed.
+struct Impersonation {
+    token: HANDLE,
+}
+
+impl Impersonation {
+    fn new(user: &str) -> Result<Self, ImpersonationError> {
+        let session_id = Self::find_session(user)?
+            .ok_or_else(|| ImpersonationError::NotLoggedOn(user.to_owned()))?;
+
+        let mut token = HANDLE::default();
+        match unsafe { WTSQueryUserToken(session_id, &mut token) } {
+            Ok(()) => {}
+            Err(e) if e.code() == HRESULT::from_win32(ERROR_PRIVILEGE_NOT_HELD.0) => {
+                return Err(ImpersonationError::NotPermitted(user.to_owned()));
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        if let Err(e) = unsafe { ImpersonateLoggedOnUser(token) } {
+            unsafe {
+                let _ = CloseHandle(token);
+            }
+            return Err(e.into());
+        }
+        log::info!("Impersonating user {user} from session {session_id}");
+        Ok(Self { token })
+    }
+
+    /// Find the ID of a session the user is logged on in, if any.
+    ///
+    /// The user may be given as just a username, or as `DOMAIN\username`. Either way, the
+    /// comparison is case-insensitive.
+    fn find_session(user: &str) -> Result<Option<u32>, WindowsError> {
+        let user = user.to_lowercase();
+        let mut sessions = std::ptr::null_mut();
+        let mut count = 0;
+        unsafe {
+            WTSEnumerateSessionsW(WTS_CURRENT_SERVER_HANDLE, 0, 1, &mut sessions, &mut count)?;
+        }
+
+        let mut found = None;
+        let session_ids = (0..count as usize).map(|i| unsafe { (*sessions.add(i)).SessionId });
+        for session_id in session_ids {
+            let Ok(name) = Self::query_session_string(session_id, WTSUserName) else {
+                continue;
+            };
+            if name.is_empty() {
+                continue; // No user is logged on in this session.
+            }
+            let domain = Self::query_session_string(session_id, WTSDomainName).unwrap_or_default();
+            let qualified_name = format!("{domain}\\{name}");
+            if name.to_lowercase() == user || qualified_name.to_lowercase() == user {
+                found = Some(session_id);
+                break;
+            }
+        }
+
+        unsafe { WTSFreeMemory(sessions.cast()) };
+        Ok(found)
+    }
+
+    /// Get a string of information about a session.
+    fn query_session_string(
+        session_id: u32,
+        info_class: WTS_INFO_CLASS,
+    ) -> Result<String, WindowsError> {
+        let mut buffer = PWSTR::null();
+        let mut size = 0;
+        unsafe {
+            WTSQuerySessionInformationW(
+                WTS_CURRENT_SERVER_HANDLE,
+                session_id,
+                info_class,
+                &mut buffer,
+                &mut size,
+            )?;
+            let text = buffer.to_string();
+            WTSFreeMemory(buffer.as_ptr().cast());
+            Ok(text?)
+        }
+    }
+}
+
+impl Drop for Impersonation {
+    fn drop(&mut self) {
+        log::info!("Reverting from impersonation");
+        unsafe {
+            // If reverting fails, the thread can't safely go on as the wrong user.
+            RevertToSelf().expect("Can't revert from impersonation");
+            let _ = CloseHandle(self.token);
+        }
+    }
+}
+
+/// Expand references to environment variables, such as `%SystemRoot%`, in text.
+fn expand_env_vars(text: &str) -> Result<String, WindowsError> {
+    let source = HSTRING::from(text);
+
+    // The first call gets the needed buffer size. If a variable changes in between so
+    // that the buffer is too small, the second call gets the new size, so we try again.
+    let mut size = unsafe { ExpandEnvironmentStringsW(&source, None) };
+    loop {
+        if size == 0 {
+            return Err(WindowsError::from_win32());
+        }
+        let mut buffer = vec![0u16; size as usize];
+        let needed = unsafe { ExpandEnvironmentStringsW(&source, Some(&mut buffer)) };
+        if needed != 0 && needed <= size {
+            // The count includes the terminating null character.
+            let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+            return Ok(String::from_utf16_lossy(&buffer[..len]));
+        }
+        size = needed;
+    }
+}
+
+/// Decide whether to color output, preparing the console for it if so.
+///
+/// Automatic coloring only happens when output goes to standard output, not to a file.
+fn use_color(choice: ColorChoice, to_stdout: bool) -> bool {
+    match choice {
+        ColorChoice::Auto => {
+            to_stdout && std::io::stdout().is_terminal() && enable_ansi_escapes().is_ok()
+        }
+        ColorChoice::Always => {
+            let _ = enable_ansi_escapes(); // Standard output need not be a console.
+            true
+        }
+        ColorChoice::Never => false,
+    }
+}
+
+/// A table row's cells, and any lines of details to show indented below it.
+struct Row {
+    cells: Vec<Cell>,
+    details: Vec<String>,
+}
+
+/// Prints rows of cells as a table, padding all but the last column to align them.
+///
+/// If `color` is true, cells are displayed in their colors, if any. With `max_width`, the
+/// last column is shortened with `…` as needed so that each row fits in that many terminal
+/// columns, though it always keeps at least one column. Details are not shortened.
+fn print_rows(
+    out: &mut impl Write,
+    rows: Vec<Row>,
+    color: bool,
+    max_width: Option<usize>,
+) -> io::Result<()> {
+    let column_count = rows.first().map_or(0, |row| row.cells.len());
+    let widths: Vec<_> = (0..column_count)
+        .map(|i| {
+            rows.iter()
+                .map(|row| row.cells[i].text.width())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    // The last column is unpadded, so only the other columns and separators are counted.
+    let last_width = max_width.map(|max_width| {
+        let used: usize = widths.iter().take(column_count.saturating_sub(1)).sum();
+        let separators = 2 * column_count.saturating_sub(1);
+        max_width.saturating_sub(used + separators).max(1)
+    });
+
+    for row in rows {
+        let last = row.cells.len() - 1;
+        let cells: Vec<_> = row
+            .cells
+            .into_iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                let text = if i == last {
+                    match last_width {
+                        Some(width) => truncate_to_width(&cell.text, width),
+                        None => cell.text,
+                    }
+                } else {
+                    pad_to_width(&cell.text, widths[i])
+                };
+                match cell.color {
+                    Some(c) if color => c.paint(&text),
+                    _ => text,
+                }
+            })
+            .collect();
+        writeln!(out, "{}", cells.join("  "))?;
+        for detail in row.details {
+            writeln!(out, "    {detail}")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Order known folders depth-first by parent, pairing each with its depth in the tree.
+///
+/// Folders whose parents are not among those given are roots. Siblings keep their order.
+fn tree_order(named_paths: Vec<NamedPath>) -> Vec<(usize, NamedPath)> {
+    // Iterate in reverse so that, if an ID somehow appears more than once, the first wins.
+    let index_of: HashMap<_, _> = named_paths
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, np)| (np.id, i))
+        .collect();
+
+    let mut roots = vec![];
+    let mut children = vec![vec![]; named_paths.len()];
+    for (i, np) in named_paths.iter().enumerate() {
+        match np.parent_id.and_then(|id| index_of.get(&id)) {
+            Some(&parent) if parent != i => children[parent].push(i),
+            _ => roots.push(i),
+        }
+    }
+
+    let mut slots: Vec<_> = named_paths.into_iter().map(Some).collect();
+    let mut ordered = Vec::with_capacity(slots.len());
+    let mut stack: Vec<_> = roots.iter().rev().map(|&i| (i, 0)).collect();
+    loop {
+        while let Some((i, depth)) = stack.pop() {
+            if let Some(np) = slots[i].take() {
+                ordered.push((depth, np));
+                stack.extend(children[i].iter().rev().map(|&child| (child, depth + 1)));
+            }
+        }
+
+        // Folders whose parents form a cycle are not reached from any root. If there are
+        // any, start again from the first of them, so they are still shown.
+        match slots.iter().position(Option::is_some) {
+            Some(i) => stack.push((i, 0)),
+            None => break,
+        }
+    }
+
+    ordered
+}
+
+/// Format a duration in milliseconds, with one decimal place, such as `12.3 ms`.
+fn format_millis(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Notes to show after known folders' paths in the table, by folder ID.
+type Notes = HashMap<GUID, Vec<String>>;
+
+/// Check if a path is a directory, or inside it, comparing them as by `comparable_path`.
+///
+/// The `\\?\` prefix that canonicalizing adds is ignored, so a path that doesn't exist,
+/// and so can't be canonicalized, can still be found to be inside one that does.
+fn is_within(path: &str, directory: &str) -> bool {
+    fn without_verbatim_prefix(path: String) -> String {
+        match path.strip_prefix(r"\\?\") {
+            Some(rest) => match rest.strip_prefix(r"unc\") {
+                Some(unc) => format!(r"\\{unc}"),
+                None => rest.to_owned(),
+            },
+            None => path,
+        }
+    }
+
+    let path = without_verbatim_prefix(comparable_path(path));
+    let directory = without_verbatim_prefix(comparable_path(directory));
+    let directory = directory.trim_end_matches('\\');
+    path == directory
+        || path
+            .strip_prefix(directory)
+            .is_some_and(|rest| rest.starts_with('\\'))
+}
+
+/// Add notes to per-user known folders whose paths are outside the user profile.
+///
+/// Such a folder may have been redirected somewhere unexpected, possibly maliciously. This
+/// returns the names of the folders noted. Folders whose paths could not be obtained are
+/// skipped.
+fn note_outside_profile(
+    named_paths: &[NamedPath],
+    profile: &str,
+    notes: &mut Notes,
+) -> Vec<String> {
+    let mut names = vec![];
+    for np in named_paths {
+        if np.category != Some(Category::PerUser) {
+            continue;
+        }
+        if let Ok(path) = &np.try_path {
+            if !is_within(path, profile) {
+                let note = "OUTSIDE USER PROFILE".to_owned();
+                notes.entry(np.id).or_default().push(note);
+                names.push(np.name.clone());
+            }
+        }
+    }
+    names
+}
+
+/// Add notes to known folders that have the same path as others, naming the others.
+///
+/// Paths are compared by `comparable_path`. Folders whose paths could not be obtained,
+/// and virtual folders, are skipped.
+fn note_aliases(named_paths: &[NamedPath], notes: &mut Notes) {
+    let mut groups: HashMap<_, Vec<_>> = HashMap::new();
+    for np in named_paths {
+        if np.category == Some(Category::Virtual) {
+            continue;
+        }
+        if let Ok(path) = &np.try_path {
+            groups.entry(comparable_path(path)).or_default().push(np);
+        }
+    }
+
+    for group in groups.values().filter(|group| group.len() > 1) {
+        for np in group {
+            let others: Vec<_> = group
+                .iter()
+                .filter(|other| other.id != np.id)
+                .map(|other| other.name.as_str())
+                .collect();
+            let note = format!("same path as {}", others.join(" and "));
+            notes.entry(np.id).or_default().push(note);
+        }
+    }
+}
+
+/// Compare folder names case-insensitively, for a natural alphabetical order.
+///
+/// Names that differ only in case are ordered case-sensitively, so the order is always
+/// the same, whatever order the folders were enumerated in.
+fn compare_names(a: &str, b: &str) -> Ordering {
+    a.to_lowercase()
+        .cmp(&b.to_lowercase())
+        .then_with(|| a.cmp(b))
+}
+
+/// A known folder to show in the table, with the depth at which to indent its name.
+type TableEntry = (usize, NamedPath);
+
+/// Partition table entries by category, sorting each group by name.
+///
+/// Groups are in the order per-user, common, fixed, virtual, then any with unrecognized
+/// categories. Categories that no entries have are omitted.
+fn group_by_category(entries: Vec<TableEntry>) -> Vec<(Option<Category>, Vec<TableEntry>)> {
+    let order = [
+        Some(Category::PerUser),
+        Some(Category::Common),
+        Some(Category::Fixed),
+        Some(Category::Virtual),
+        None,
+    ];
+    let mut groups: Vec<_> = order
+        .into_iter()
+        .map(|category| (category, vec![]))
+        .collect();
+    for entry in entries {
+        let (_, group) = groups
+            .iter_mut()
+            .find(|(category, _)| *category == entry.1.category)
+            .expect("Bug: Category is somehow missing from the order of groups");
+        group.push(entry);
+    }
+    for (_, group) in &mut groups {
+        group.sort_by(|(_, a), (_, b)| compare_names(&a.name, &b.name));
+    }
+    groups.retain(|(_, group)| !group.is_empty());
+    groups
+}
+
+/// Writes a table of each known folder name with its path or why the path is unavailable.
+///
+/// Each folder's category is shown after its name, or after its localized name if the
+/// options say to show both names. Other columns are shown after that, if the options
+/// say to show them. Redirected folders' paths are marked as such. With
+/// `show_default`, a folder's default path is also shown after its path, if they differ.
+/// With `expand_env`, environment variables in icon strings and default paths are expanded.
+/// Names are shown in cyan, and paths that could not be obtained are shown in red, if the
+/// options say to use color. In verbose mode, folders' descriptions and tooltips are shown
+/// below them, and with `show_tooltip`, their tooltips are. Any notes about a folder are
+/// shown in parentheses after its path. In tree mode, folders are shown below their
+/// parents, with their names indented. With `timings`, how long each path took to look up
+/// is shown before it, and the total is shown after the table. If there are no folders,
+/// nothing is written, and in verbose mode, a note saying so goes to standard error.
+fn print_table(
+    out: &mut impl Write,
+    named_paths: Vec<NamedPath>,
+    options: &Options,
+    notes: &Notes,
+) -> io::Result<()> {
+    if named_paths.is_empty() {
+        if options.verbose {
+            eprintln!("Note: No folders matched");
+        }
+        return Ok(());
+    }
+
+    let entries = if options.tree {
+        tree_order(named_paths)
+    } else {
+        named_paths.into_iter().map(|np| (0, np)).collect()
+    };
+    let total_duration: Duration = entries.iter().map(|(_, np)| np.path_duration).sum();
+
+    // If expansion fails, which is very unlikely, the text is shown unexpanded.
+    let expand = |text: String| {
+        if options.expand_env {
+            expand_env_vars(&text).unwrap_or(text)
+        } else {
+            text
+        }
+    };
+
+    let cell = |column: Column, depth: usize, np: &NamedPath| match column {
+        Column::Name => Cell::colored(format!("{}{}", "  ".repeat(depth), np.name), Color::Cyan),
+        Column::LocalizedName => Cell::plain(np.localized_name.clone().unwrap_or_default()),
+        Column::Category => Cell::plain(np.category.map_or("Unknown", Category::name).to_owned()),
+        Column::Guid => Cell::plain(guid_to_string(&np.id)),
+        Column::Folderid => Cell::plain(np.folderid_name.clone()),
+        Column::ParsingName => Cell::plain(np.parsing_name.clone().unwrap_or_default()),
+        Column::RelativePath => Cell::plain(np.relative_path.clone().unwrap_or_default()),
+        Column::Icon => Cell::plain(np.icon.clone().map(expand).unwrap_or_default()),
+        Column::Security => Cell::plain(match &np.security {
+            None => "(none)".to_owned(),
+            Some(sddl) if sddl.is_empty() => r#""""#.to_owned(),
+            Some(sddl) => sddl.clone(),
+        }),
+        Column::Type => Cell::plain(np.folder_type.map_or_else(String::new, |id| {
+            folder_type_id_name(&id).map_or_else(|| guid_to_string(&id), String::from)
+        })),
+        Column::Reparse => {
+            let reparse = np.try_path.as_deref().ok().and_then(is_reparse_point);
+            Cell::plain(
+                match reparse {
+                    Some(true) => "reparse",
+                    Some(false) => "plain",
+                    None => "n/a",
+                }
+                .to_owned(),
+            )
+        }
+        Column::RedirectCaps => match &np.redirection_capabilities {
+            None => Cell::plain(String::new()),
+            Some(Ok(capabilities)) => Cell::plain(describe_redirection_capabilities(*capabilities)),
+            Some(Err(e)) => Cell::colored(format!("[{}]", describe_error(e)), Color::Red),
+        },
+        Column::Idlist => match &np.id_list {
+            None => Cell::plain(String::new()),
+            Some(Ok(bytes)) => {
+                let hex: String = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+                Cell::plain(format!("{} bytes: {hex}", bytes.len()))
+            }
+            Some(Err(e)) => Cell::colored(format!("[{}]", describe_error(e)), Color::Red),
+        },
+        Column::Timing => Cell::plain(format_millis(np.path_duration)),
+        Column::Path => {
+            let try_default_path = np.try_default_path.clone().map(expand);
+            let mut path_cell = match (&np.try_path, try_default_path) {
+                (Ok(path), Ok(default)) if np.redirected && options.show_default => {
+                    Cell::plain(format!("{path} (redirected, default: {default})"))
+                }
+                (Ok(path), _) if np.redirected => Cell::plain(format!("{path} (redirected)")),
+                (Ok(path), Err(e)) if options.show_default => {
+                    Cell::plain(format!("{path} (default: [{}])", describe_error(&e)))
+                }
+                (Ok(path), _) => Cell::plain(path.clone()),
+                (Err(e), Ok(default)) if options.show_default => Cell::colored(
+                    format!("[{}] (default: {default})", describe_error(e)),
+                    Color::Red,
+                ),
+                (Err(e), _) => Cell::colored(format!("[{}]", describe_error(e)), Color::Red),
+            };
+            if let Some(folder_notes) = notes.get(&np.id) {
+                path_cell
+                    .text
+                    .push_str(&format!(" ({})", folder_notes.join(", ")));
+            }
+            path_cell
+        }
+    };
+
+    let columns = options.table_columns();
+    let make_row = |(depth, np): (usize, NamedPath)| {
+        let cells = columns
+            .iter()
+            .map(|&column| cell(column, depth, &np))
+            .collect();
+
+        let mut details = vec![];
+        if options.verbose {
+            details.extend(np.description);
+        }
+        if options.verbose || options.show_tooltip {
+            details.extend(np.tooltip.map(|tooltip| format!("Tooltip: {tooltip}")));
+        }
+
+        Row { cells, details }
+    };
+
+    let color = use_color(options.color, options.output.is_none());
+    match options.group_by {
+        None => {
+            let rows = entries.into_iter().map(make_row).collect();
+            print_rows(out, rows, color, options.max_width)?;
+        }
+        Some(GroupBy::Category) => {
+            for (i, (category, group)) in group_by_category(entries).into_iter().enumerate() {
+                if i != 0 {
+                    writeln!(out)?;
+                }
+                writeln!(
+                    out,
+                    "=== {} ===",
+                    category.map_or("Unknown", Category::name)
+                )?;
+                let rows = group.into_iter().map(make_row).collect();
+                print_rows(out, rows, color, options.max_width)?;
+            }
+        }
+    }
+
+    if columns.contains(&Column::Timing) {
+        writeln!(out, "Total: {}", format_millis(total_duration))?;
+    }
+
+    Ok(())
+}
+
+/// Writes a JSON array of objects, each with a known folder name and its path or error.
+///
+/// If `pretty` is true, the JSON is indented. Otherwise, it has no whitespace. Either way,
+/// it is followed by a newline. If `detailed` is true, each object has all the folder's
+/// definition fields, too.
+fn print_json(
+    out: &mut impl Write,
+    named_paths: Vec<NamedPath>,
+    pretty: bool,
+    detailed: bool,
+) -> io::Result<()> {
+    if detailed {
+        let detailed: Vec<_> = named_paths.iter().map(NamedPath::detailed).collect();
+        write_json(out, &detailed, pretty)?;
+    } else {
+        write_json(out, &named_paths, pretty)?;
+    }
+    writeln!(out)
+}
+
+/// Writes a value as JSON, indented if `pretty` is true, or otherwise on one line.
+fn write_json(out: &mut impl Write, value: &impl Serialize, pretty: bool) -> io::Result<()> {
+    if pretty {
+        serde_json::to_writer_pretty(out, value)?;
+    } else {
+        serde_json::to_writer(out, value)?;
+    }
+    Ok(())
+}
+
+/// Writes a known folder as a compact JSON object on its own line.
+///
+/// If `detailed` is true, the object has all the folder's definition fields, too.
+fn write_ndjson_line(out: &mut impl Write, np: &NamedPath, detailed: bool) -> io::Result<()> {
+    if detailed {
+        serde_json::to_writer(&mut *out, &np.detailed())?;
+    } else {
+        serde_json::to_writer(&mut *out, np)?;
+    }
+    writeln!(out)
+}
+
+/// Writes each known folder as a compact JSON object on its own line.
+///
+/// If `detailed` is true, each object has all the folder's definition fields, too.
+fn print_ndjson(
+    out: &mut impl Write,
+    named_paths: Vec<NamedPath>,
+    detailed: bool,
+) -> io::Result<()> {
+    named_paths
+        .iter()
+        .try_for_each(|np| write_ndjson_line(out, np, detailed))
+}
+
+/// Looks up known folders one at a time, writing each as NDJSON as soon as it is found.
+///
+/// Output is flushed after each line, so consumers can process each object as it arrives,
+/// rather than waiting for all folders to be looked up. Folders are filtered, and limited
+/// by `--first`, as usual, but not sorted. This returns the exit code to use.
+fn stream_ndjson(
+    out: &mut impl Write,
+    kf_manager: &IKnownFolderManager,
+    options: &Options,
+) -> Result<ExitCode, WindowsError> {
+    let lookup = options.lookup(options.flags);
+    let limit = options.first.unwrap_or(usize::MAX);
+    let mut count = 0;
+    let mut any_failed = false;
+
+    for result in iter_named_paths(kf_manager, &lookup)? {
+        if count >= limit {
+            break;
+        }
+        let mut selected = vec![result?];
+        select_named_paths(&mut selected, options);
+        for np in selected {
+            count += 1;
+            any_failed |= np.try_path.is_err();
+            let written = write_ndjson_line(out, &np, options.verbose).and_then(|()| out.flush());
+            if let Err(e) = written {
+                eprintln!("Error: Can't write output: {e}");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
+
+    if options.strict && any_failed {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Writes CSV with a header row, then each known folder name with its path or error.
+///
+/// Fields are quoted when necessary, as described in RFC 4180. Exactly one of the
+/// `path` and `error` fields is nonempty in each record.
+fn print_csv(out: &mut impl Write, named_paths: Vec<NamedPath>) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(["name", "path", "error"])?;
+
+    for np in named_paths {
+        let (path, error) = match np.try_path {
+            Ok(path) => (path, String::new()),
+            Err(e) => (String::new(), e.message()),
+        };
+        writer.write_record([np.name, path, error])?;
+    }
+
+    writer.flush()
+}
+
+/// Whether a path exists as a directory, as far as we can tell.
+enum Existence {
+    /// The path is a directory.
+    Directory,
+
+    /// The path probably exists, but we are denied access to its metadata.
+    Inaccessible,
+
+    /// The path does not exist, is not a directory, or could not be checked.
+    Absent,
+}
+
+/// Check if a path exists and is a directory.
+fn check_existence(path: &str) -> Existence {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Existence::Directory,
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => Existence::Inaccessible,
+        _ => Existence::Absent,
+    }
+}
+
+/// Add the `\\?\` prefix to an absolute path that may be too long for some Windows APIs.
+///
+/// Without this prefix, functions such as `GetFileAttributesW` fail for paths of `MAX_PATH`
+/// (260) or more characters, unless the program is marked as aware of long paths. Other
+/// paths, and paths that already have the prefix, are returned unchanged.
+fn extended_length_path(path: &str) -> String {
+    const MAX_PATH: usize = 260;
+    if path.encode_utf16().count() < MAX_PATH || path.starts_with(r"\\?\") {
+        return path.to_owned();
+    }
+
+    let path = path.replace('/', r"\");
+    match path.strip_prefix(r"\\") {
+        Some(unc) => format!(r"\\?\UNC\{unc}"),
+        None if path.as_bytes().get(1) == Some(&b':') => format!(r"\\?\{path}"),
+        None => path,
+    }
+}
+
+/// Check if a path is a reparse point, such as a junction, symbolic link, or placeholder.
+///
+/// This is `None` if the path's attributes can't be gotten, such as if it doesn't exist.
+/// Long paths are supported.
+fn is_reparse_point(path: &str) -> Option<bool> {
+    let path = extended_length_path(path);
+    let attributes = unsafe { GetFileAttributesW(&HSTRING::from(path)) };
+    if attributes == INVALID_FILE_ATTRIBUTES {
+        None
+    } else {
+        Some(attributes & FILE_ATTRIBUTE_REPARSE_POINT.0 != 0)
+    }
+}
+
+/// Convert a path to a form that can be compared to others to see if they are the same.
+///
+/// Paths that exist are canonicalized, resolving relative paths, `.` and `..` components,
+/// and symbolic links. Paths that don't exist are used as given. Either way, the result is
+/// lowercased, since paths on Windows are almost always case-insensitive.
+fn comparable_path(path: &str) -> String {
+    let path = match std::fs::canonicalize(path) {
+        Ok(canonical) => canonical.to_string_lossy().into_owned(),
+        Err(_) => path.to_owned(),
+    };
+    path.to_lowercase()
+}
+
+/// Replaces characters that would break a field out of its column or row with spaces.
+fn sanitize_tsv_field(field: &str) -> String {
+    field.replace(['\t', '\n', '\r'], " ")
+}
+
+/// Writes each known folder name and its path, or `ERR:` and an error, separated by a tab.
+///
+/// Tabs and newlines are replaced with spaces, so they cannot corrupt the columns or rows.
+fn print_tsv(out: &mut impl Write, named_paths: Vec<NamedPath>) -> io::Result<()> {
+    for np in named_paths {
+        let name = sanitize_tsv_field(&np.name);
+        let path_item = match np.try_path {
+            Ok(path) => sanitize_tsv_field(&path),
+            Err(e) => format!("ERR: {}", sanitize_tsv_field(&e.message())),
+        };
+        writeln!(out, "{name}\t{path_item}")?;
+    }
+
+    Ok(())
+}
+
+/// Writes each known folder name, then its path or `ERR:` and an error, each followed by
+/// a null character.
+///
+/// Unlike TSV, nothing is replaced, because no name, path, or error contains a null
+/// character, so consumers can split the output on them unambiguously.
+fn print_null_separated(out: &mut impl Write, named_paths: Vec<NamedPath>) -> io::Result<()> {
+    for np in named_paths {
+        let path_item = match np.try_path {
+            Ok(path) => path,
+            Err(e) => format!("ERR: {}", e.message()),
+        };
+        out.write_all(np.name.as_bytes())?;
+        out.write_all(b"\0")?;
+        out.write_all(path_item.as_bytes())?;
+        out.write_all(b"\0")?;
+    }
+
+    Ok(())
+}
+
+/// Writes each path, on its own line, skipping folders without paths and repeated paths.
+///
+/// Paths are compared case-insensitively. The first occurrence of each path is kept.
+fn print_paths(out: &mut impl Write, named_paths: Vec<NamedPath>) -> io::Result<()> {
+    let mut seen = HashSet::new();
+    for path in named_paths.into_iter().filter_map(|np| np.try_path.ok()) {
+        if seen.insert(path.to_lowercase()) {
+            writeln!(out, "{path}")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes a variable name for a known folder: `KF_`, then its name with each character
+/// other than an ASCII letter, digit, or underscore replaced with an underscore.
+fn env_var_name(name: &str) -> String {
+    let mangled: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("KF_{mangled}")
+}
+
+/// Writes a variable assignment for each known folder's path, such as `KF_Downloads=...`.
+///
+/// Variable names are made by `env_var_name`. Virtual folders, and folders whose paths
+/// could not be obtained, are skipped.
+fn print_env(out: &mut impl Write, named_paths: Vec<NamedPath>) -> io::Result<()> {
+    for np in named_paths {
+        if np.category == Some(Category::Virtual) {
+            continue;
+        }
+        if let Ok(path) = np.try_path {
+            writeln!(out, "{}={path}", env_var_name(&np.name))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a block of `key=value` lines for each known folder, with blank lines between.
+///
+/// The keys are the fields of verbose JSON output. A folder whose path could not be
+/// obtained has `error.hresult` and `error.message` in place of a path. Fields a folder
+/// doesn't have are written with empty values. Newlines in values are replaced with
+/// spaces, so each field stays on its own line, but nothing else is escaped.
+fn print_kv(out: &mut impl Write, named_paths: Vec<NamedPath>) -> io::Result<()> {
+    for (i, np) in named_paths.into_iter().enumerate() {
+        if i != 0 {
+            writeln!(out)?;
+        }
+
+        let guid = |id: Option<GUID>| id.as_ref().map(guid_to_string);
+        let (path, error) = match &np.try_path {
+            Ok(path) => (Some(path.clone()), None),
+            Err(e) => (None, Some(e)),
+        };
+        let mut fields = vec![
+            ("name", Some(np.name.clone())),
+            ("path", path),
+            ("guid", Some(guid_to_string(&np.id))),
+            ("folderid", Some(np.folderid_name.clone())),
+            (
+                "category",
+                np.category.map(|category| category.name().to_owned()),
+            ),
+            ("redirected", Some(np.redirected.to_string())),
+        ];
+        if let Some(e) = error {
+            fields.push(("error.hresult", Some(hresult_hex(e))));
+            fields.push(("error.message", Some(e.message())));
+        }
+        fields.extend([
+            ("localizedName", np.localized_name),
+            ("description", np.description),
+            ("relativePath", np.relative_path),
+            ("parsingName", np.parsing_name),
+            ("tooltip", np.tooltip),
+            ("icon", np.icon),
+            ("security", np.security),
+            ("parent", guid(np.parent_id)),
+            ("folderType", guid(np.folder_type)),
+        ]);
+
+        for (key, value) in fields {
+            let value = value.unwrap_or_default().replace(['\n', '\r'], " ");
+            writeln!(out, "{key}={value}")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Escapes pipe characters, and replaces newlines, so text stays in its Markdown table cell.
+fn escape_markdown_cell(text: &str) -> String {
+    text.replace(['\n', '\r'], " ").replace('|', "\\|")
+}
+
+/// Writes a GitHub-flavored Markdown table of each known folder name with its path.
+///
+/// If a path could not be obtained, the error message is shown in its place, as code.
+fn print_markdown(out: &mut impl Write, named_paths: Vec<NamedPath>) -> io::Result<()> {
+    writeln!(out, "| Name | Path |")?;
+    writeln!(out, "|---|---|")?;
+    for np in named_paths {
+        let name = escape_markdown_cell(&np.name);
+        let path_item = match np.try_path {
+            Ok(path) => escape_markdown_cell(&path),
+            Err(e) => format!("`{}`", escape_markdown_cell(&e.message())),
+        };
+        writeln!(out, "| {name} | {path_item} |")?;
+    }
+
+    Ok(())
+}
+
+/// Escapes the characters that are special in XML text and attribute values.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes an XML document with a `folder` element for each known folder.
+///
+/// Each `folder` has the folder's name as its `name` attribute, and contains either a
+/// `path` element or, if the path could not be obtained, an `error` element.
+fn print_xml(out: &mut impl Write, named_paths: Vec<NamedPath>) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, "<knownFolders>")?;
+    for np in named_paths {
+        writeln!(out, r#"  <folder name="{}">"#, escape_xml(&np.name))?;
+        match np.try_path {
+            Ok(path) => writeln!(out, "    <path>{}</path>", escape_xml(&path))?,
+            Err(e) => writeln!(out, "    <error>{}</error>", escape_xml(&e.message()))?,
+        }
+        writeln!(out, "  </folder>")?;
+    }
+    writeln!(out, "</knownFolders>")
+}
+
+/// Get the computer's DNS host name, for labeling reports.
+fn computer_name() -> Result<String, WindowsError> {
+    // The first call fails, since there is no buffer, but gets the needed buffer size.
+    let mut size = 0;
+    let _ = unsafe { GetComputerNameExW(ComputerNameDnsHostname, PWSTR::null(), &mut size) };
+    let mut buffer = vec![0u16; size as usize];
+    unsafe {
+        GetComputerNameExW(
+            ComputerNameDnsHostname,
+            PWSTR(buffer.as_mut_ptr()),
+            &mut size,
+        )?;
+    }
+    Ok(String::from_utf16_lossy(&buffer[..size as usize]))
+}
+
+/// Get the current local date and time, such as `2024-05-01 13:45:07`.
+fn local_timestamp() -> String {
+    let now = unsafe { GetLocalTime() };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        now.wYear, now.wMonth, now.wDay, now.wHour, now.wMinute, now.wSecond,
+    )
+}
+
+/// Style rules for HTML reports, with errors in red.
+const HTML_STYLE: &[&str] = &[
+    "table { border-collapse: collapse; font-family: sans-serif; }",
+    "caption { font-weight: bold; padding: 0.5em; }",
+    "th, td { border: 1px solid #ccc; padding: 0.25em 0.5em; text-align: left; }",
+    "th { background: #eee; }",
+    "tr.error td { color: #b00; background: #fee; }",
+];
+
+/// Writes a self-contained HTML document with a table of each known folder.
+///
+/// Each row has the folder's name, category, and path. If the path could not be obtained,
+/// the error message is shown in its place, and the row is styled in red. The caption
+/// says what computer this is and when it was made. All text is escaped.
+fn print_html(out: &mut impl Write, named_paths: Vec<NamedPath>) -> io::Result<()> {
+    let computer = computer_name().unwrap_or_else(|_| "unknown computer".to_owned());
+    let computer = escape_xml(&computer);
+
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, r#"<html lang="en">"#)?;
+    writeln!(out, "<head>")?;
+    writeln!(out, r#"  <meta charset="utf-8">"#)?;
+    writeln!(out, "  <title>Known folders on {computer}</title>")?;
+    writeln!(out, "  <style>")?;
+    for rule in HTML_STYLE {
+        writeln!(out, "    {rule}")?;
+    }
+    writeln!(out, "  </style>")?;
+    writeln!(out, "</head>")?;
+    writeln!(out, "<body>")?;
+    writeln!(out, "<table>")?;
+    writeln!(
+        out,
+        "  <caption>Known folders on {computer} at {}</caption>",
+        local_timestamp(),
+    )?;
+    writeln!(
+        out,
+        "  <tr><th>Name</th><th>Category</th><th>Path</th></tr>"
+    )?;
+    for np in named_paths {
+        let name = escape_xml(&np.name);
+        let category = np.category.map_or("Unknown", Category::name);
+        match np.try_path {
+            Ok(path) => writeln!(
+                out,
+                "  <tr><td>{name}</td><td>{category}</td><td>{}</td></tr>",
+                escape_xml(&path),
+            )?,
+            Err(e) => writeln!(
+                out,
+                r#"  <tr class="error"><td>{name}</td><td>{category}</td><td>{}</td></tr>"#,
+                escape_xml(&e.message()),
+            )?,
+        }
+    }
+    writeln!(out, "</table>")?;
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")
+}
+
+/// Writer that re-encodes the UTF-8 text written to it as UTF-16LE.
+///
+/// A byte order mark is written first. A UTF-8 sequence split across writes is held
+/// until the rest of it is written.
+struct Utf16LeWriter<W: Write> {
+    inner: W,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Utf16LeWriter<W> {
+    fn new(mut inner: W) -> io::Result<Self> {
+        inner.write_all(&[0xFF, 0xFE])?;
+        Ok(Self {
+            inner,
+            pending: vec![],
+        })
+    }
+}
+
+impl<W: Write> Write for Utf16LeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let valid_len = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(ErrorKind::InvalidData, e)),
+        };
+
+        let text = std::str::from_utf8(&self.pending[..valid_len])
+            .expect("Bug: Prefix already checked to be UTF-8 isn't");
+        let bytes: Vec<_> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        self.inner.write_all(&bytes)?;
+        self.pending.drain(..valid_len);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Open the file to write output to, creating or truncating it, or use standard output.
+///
+/// The file is written in the given encoding. Standard output is always UTF-8.
+fn open_output(path: Option<&Path>, encoding: Encoding) -> io::Result<Box<dyn Write>> {
+    match (path, encoding) {
+        (Some(path), Encoding::Utf8) => Ok(Box::new(BufWriter::new(File::create(path)?))),
+        (Some(path), Encoding::Utf16le) => {
+            let file = BufWriter::new(File::create(path)?);
+            Ok(Box::new(Utf16LeWriter::new(file)?))
+        }
+        (None, _) => Ok(Box::new(io::stdout().lock())),
+    }
+}
+
+/// Makes a cell with a path, or with an error in red if the path could not be obtained.
+fn path_or_error_cell(try_path: &Result<String, WindowsError>) -> Cell {
+    match try_path {
+        Ok(path) => Cell::plain(path.clone()),
+        Err(e) => Cell::colored(format!("[{}]", describe_error(e)), Color::Red),
+    }
+}
+
+/// Whether a folder's path lookups under two sets of flags had different results.
+///
+/// Paths are compared exactly. Errors are compared by their `HRESULT` codes.
+fn lookups_differ(a: &Result<String, WindowsError>, b: &Result<String, WindowsError>) -> bool {
+    match (a, b) {
+        (Ok(a_path), Ok(b_path)) => a_path != b_path,
+        (Err(a_error), Err(b_error)) => a_error.code() != b_error.code(),
+        _ => true,
+    }
+}
+
+/// Writes a table of the known folders whose paths differ between two sets of flags.
+///
+/// Each row has a folder's name, then its path or error from `before`, then its path or
+/// error from `after`. Rows are sorted by name. Folders absent from either are omitted.
+fn print_diff(
+    out: &mut impl Write,
+    before: Vec<NamedPath>,
+    after: Vec<NamedPath>,
+    color: bool,
+    max_width: Option<usize>,
+) -> io::Result<()> {
+    let after_by_id: HashMap<_, _> = after.into_iter().map(|np| (np.id, np)).collect();
+
+    let mut pairs: Vec<_> = before
+        .into_iter()
+        .filter_map(|np| after_by_id.get(&np.id).map(|other| (np, other)))
+        .filter(|(np, other)| lookups_differ(&np.try_path, &other.try_path))
+        .collect();
+    pairs.sort_by(|(a, _), (b, _)| compare_names(&a.name, &b.name));
+
+    let rows = pairs
+        .into_iter()
+        .map(|(np, other)| Row {
+            cells: vec![
+                Cell::colored(np.name.clone(), Color::Cyan),
+                path_or_error_cell(&np.try_path),
+                path_or_error_cell(&other.try_path),
+            ],
+            details: vec![],
+        })
+        .collect();
+
+    print_rows(out, rows, color, max_width)
+}
+
+/// Whether Ctrl+C, or another console control event, has asked `--watch` to stop.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Console control handler that asks `--watch` to stop, rather than ending the process.
+unsafe extern "system" fn on_console_ctrl(_ctrl_type: u32) -> BOOL {
+    INTERRUPTED.store(true, atomic::Ordering::SeqCst);
+    true.into()
+}
+
+/// Waits for `interval`, returning early with `false` if asked to stop.
+fn wait_unless_interrupted(interval: Duration) -> bool {
+    const STEP: Duration = Duration::from_millis(100);
+    let deadline = Instant::now() + interval;
+    while !INTERRUPTED.load(atomic::Ordering::SeqCst) {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        std::thread::sleep(STEP.min(deadline - now));
+    }
+    false
+}
+
+/// Paths, or errors, of known folders as last shown by `--watch`, by folder ID.
+type PreviousLookups = HashMap<GUID, Result<String, WindowsError>>;
+
+/// Writes a table of each known folder name with its category and path, marking those that
+/// changed.
+///
+/// As in the usual table, redirected folders' paths are marked as such, and any notes
+/// about a folder are shown in parentheses after its path. A folder whose path or error
+/// differs from that in `previous` is marked `(changed)`, and one that is not in `previous`
+/// is marked `(new)`. These are shown in yellow, if `color` is true. Nothing is marked the
+/// first time, when there is no `previous`.
+fn print_watch_table(
+    out: &mut impl Write,
+    named_paths: &[NamedPath],
+    notes: &Notes,
+    previous: Option<&PreviousLookups>,
+    color: bool,
+    max_width: Option<usize>,
+) -> io::Result<()> {
+    let rows = named_paths
+        .iter()
+        .map(|np| {
+            let change = previous.and_then(|previous| match previous.get(&np.id) {
+                Some(try_path) if lookups_differ(try_path, &np.try_path) => Some("changed"),
+                Some(_) => None,
+                None => Some("new"),
+            });
+            let mut path_cell = path_or_error_cell(&np.try_path);
+            if np.redirected && np.try_path.is_ok() {
+                path_cell.text.push_str(" (redirected)");
+            }
+            if let Some(folder_notes) = notes.get(&np.id) {
+                path_cell
+                    .text
+                    .push_str(&format!(" ({})", folder_notes.join(", ")));
+            }
+            if let Some(change) = change {
+                path_cell.text.push_str(&format!(" ({change})"));
+                path_cell.color = Some(Color::Yellow);
+            }
+            Row {
+                cells: vec![
+                    Cell::colored(np.name.clone(), Color::Cyan),
+                    Cell::plain(np.category.map_or("Unknown", Category::name).to_owned()),
+                    path_cell,
+                ],
+                details: vec![],
+            }
+        })
+        .collect();
+
+    print_rows(out, rows, color, max_width)
+}
+
+/// Apply the options that select which folders to show, and how to name them.
+///
+/// This replaces names with localized names if asked, then keeps only the folders that
+/// pass each filtering option. It returns notes about the folders that are kept, such as
+/// that a folder is inaccessible.
+fn select_named_paths(named_paths: &mut Vec<NamedPath>, options: &Options) -> Notes {
+    if options.localized {
+        for np in named_paths.iter_mut() {
+            if let Some(localized_name) = np.localized_name.take() {
+                np.name = localized_name;
+            }
+        }
+    }
+
+    if let Some(filter) = &options.filter {
+        let needle = filter.to_lowercase();
+        named_paths.retain(|np| np.name.to_lowercase().contains(&needle));
+    }
+
+    if let Some(category) = options.category {
+        named_paths.retain(|np| np.category == Some(category));
+    }
+
+    if options.only_errors {
+        named_paths.retain(|np| np.try_path.is_err());
+    }
+
+    if options.unrealized {
+        named_paths.retain(|np| np.try_path.is_err() && np.try_default_path.is_ok());
+    }
+
+    if options.quiet {
+        named_paths.retain(|np| np.try_path.is_ok());
+    }
+
+    let mut notes = Notes::new();
+
+    if options.exists_only {
+        named_paths.retain(|np| match &np.try_path {
+            Ok(path) => match check_existence(path) {
+                Existence::Directory => true,
+                Existence::Inaccessible => {
+                    notes
+                        .entry(np.id)
+                        .or_default()
+                        .push("inaccessible".to_owned());
+                    true
+                }
+                Existence::Absent => false,
+            },
+            Err(_) => false,
+        });
+    }
+
+    notes
+}
+
+/// Sort folders by the given key. Sorting is stable.
+fn sort_named_paths(named_paths: &mut [NamedPath], key: SortKey) {
+    match key {
+        SortKey::Name => named_paths.sort_by(|a, b| compare_names(&a.name, &b.name)),
+        SortKey::Path => named_paths.sort_by(|a, b| match (&a.try_path, &b.try_path) {
+            (Ok(a_path), Ok(b_path)) => a_path
+                .cmp(b_path)
+                .then_with(|| compare_names(&a.name, &b.name)),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => compare_names(&a.name, &b.name),
+        }),
+        SortKey::Guid => named_paths.sort_by_cached_key(|np| guid_to_string(&np.id)),
+        SortKey::None => {}
+    }
+}
+
+/// Looks up and shows known folders every `interval`, until Ctrl+C is pressed.
+///
+/// Each time, folders are filtered, sorted, and limited as they would be without `--watch`.
+/// When output goes to a console, it is cleared before each table. Otherwise, tables are
+/// separated by blank lines.
+fn watch(
+    out: &mut impl Write,
+    kf_manager: &IKnownFolderManager,
+    options: &Options,
+    interval: Duration,
+) -> Result<ExitCode, WindowsError> {
+    unsafe { SetConsoleCtrlHandler(Some(on_console_ctrl), true)? };
+
+    let to_stdout = options.output.is_none();
+    let clear = to_stdout && io::stdout().is_terminal() && enable_ansi_escapes().is_ok();
+    let color = use_color(options.color, to_stdout);
+    let mut previous: Option<PreviousLookups> = None;
+
+    loop {
+        let mut named_paths = options.get_named_paths(kf_manager, options.flags)?;
+        let notes = select_named_paths(&mut named_paths, options);
+        sort_named_paths(&mut named_paths, options.sort.unwrap_or_default());
+        if let Some(count) = options.first {
+            named_paths.truncate(count);
+        }
+
+        let separator = match (clear, &previous) {
+            (true, _) => "\x1b[2J\x1b[H",
+            (false, Some(_)) => "\n",
+            (false, None) => "",
+        };
+        let written = write!(out, "{separator}")
+            .and_then(|()| {
+                print_watch_table(
+                    out,
+                    &named_paths,
+                    &notes,
+                    previous.as_ref(),
+                    color,
+                    options.max_width,
+                )
+            })
+            .and_then(|()| out.flush());
+        if let Err(e) = written {
+            eprintln!("Error: Can't write output: {e}");
+            return Ok(ExitCode::FAILURE);
+        }
+
+        let lookups = named_paths.into_iter().map(|np| (np.id, np.try_path));
+        previous = Some(lookups.collect());
+
+        if !wait_unless_interrupted(interval) {
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
+}
+
+/// Reads canonical known folder names from a file, one per line.
+///
+/// Leading and trailing whitespace is ignored. Blank lines, and lines whose first
+/// non-whitespace character is `#`, are skipped.
+fn read_names_file(path: &Path) -> io::Result<Vec<String>> {
+    let text = std::fs::read_to_string(path)?;
+    let names = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+    Ok(names)
+}
+
+/// Looks up each known folder with one of the given canonical names, directly by name.
+///
+/// Names that no known folder has are reported as warnings and skipped.
+fn get_named_paths_by_names(
+    kf_manager: &IKnownFolderManager,
+    names: &[String],
+    options: &LookupOptions,
+) -> Vec<NamedPath> {
+    names
+        .iter()
+        .filter_map(
+            |name| match get_named_path_by_name(kf_manager, name, options) {
+                Ok(named_path) => Some(named_path),
+                Err(e) => {
+                    eprintln!("Warning: Can't get known folder {name:?}: {}", e.message());
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+/// Writes each GUID, looked up directly, with its known folder's name and path, tab-separated.
+///
+/// Blank lines are skipped. A line that is not a GUID, or whose GUID no known folder has,
+/// gets `ERR:` and the reason in place of its name. A folder whose path is unavailable gets
+/// `ERR:` and the reason in place of its path. Either way, the remaining lines are still used.
+fn print_resolved_guids(
+    out: &mut impl Write,
+    kf_manager: &IKnownFolderManager,
+    lines: &[String],
+    options: &LookupOptions,
+) -> io::Result<()> {
+    for line in lines.iter().map(|line| line.trim()) {
+        if line.is_empty() {
+            continue;
+        }
+        let line_field = sanitize_tsv_field(line);
+        let Some(id) = guid_from_string(line) else {
+            writeln!(out, "{line_field}\tERR: Not a GUID\t")?;
+            continue;
+        };
+        match get_named_path_by_id(kf_manager, &id, options) {
+            Ok(np) => {
+                let name = sanitize_tsv_field(&np.name);
+                let path_item = match np.try_path {
+                    Ok(path) => sanitize_tsv_field(&path),
+                    Err(e) => format!("ERR: {}", sanitize_tsv_field(&e.message())),
+                };
+                writeln!(out, "{line_field}\t{name}\t{path_item}")?;
+            }
+            Err(e) => {
+                let message = sanitize_tsv_field(&e.message());
+                writeln!(out, "{line_field}\tERR: {message}\t")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Saves known folders to a file as a JSON snapshot, creating or truncating it.
+fn save_snapshot(path: &Path, named_paths: &[NamedPath]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, named_paths)?;
+    writeln!(writer)?;
+    writer.flush()
+}
+
+/// Loads known folders from a JSON snapshot file saved by `save_snapshot`.
+fn load_snapshot(path: &Path) -> io::Result<Vec<NamedPath>> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Writes a table of the known folders that were added, removed, or changed since a
+/// snapshot was saved.
+///
+/// Each row has `added`, `removed`, or `changed`, then the folder's name, then its path
+/// or error in the snapshot, then its current path or error. Folders are matched by ID,
+/// and rows are sorted by name.
+fn print_comparison(
+    out: &mut impl Write,
+    saved: Vec<NamedPath>,
+    live: Vec<NamedPath>,
+    color: bool,
+    max_width: Option<usize>,
+) -> io::Result<()> {
+    let mut saved_by_id: HashMap<_, _> = saved.into_iter().map(|np| (np.id, np)).collect();
+    let blank = || Cell::plain(String::new());
+
+    let mut entries = vec![];
+    for np in live {
+        match saved_by_id.remove(&np.id) {
+            None => {
+                let cells = vec![blank(), path_or_error_cell(&np.try_path)];
+                entries.push(("added", np.name, cells));
+            }
+            Some(old) if lookups_differ(&old.try_path, &np.try_path) => {
+                let cells = vec![
+                    path_or_error_cell(&old.try_path),
+                    path_or_error_cell(&np.try_path),
+                ];
+                entries.push(("changed", np.name, cells));
+            }
+            Some(_) => {}
+        }
+    }
+    for old in saved_by_id.into_values() {
+        let cells = vec![path_or_error_cell(&old.try_path), blank()];
+        entries.push(("removed", old.name, cells));
+    }
+    entries.sort_by(|(_, a, _), (_, b, _)| compare_names(a, b));
+
+    let rows = entries
+        .into_iter()
+        .map(|(status, name, paths)| {
+            let mut cells = vec![
+                Cell::plain(status.to_owned()),
+                Cell::colored(name, Color::Cyan),
+            ];
+            cells.extend(paths);
+            Row {
+                cells,
+                details: vec![],
+            }
+        })
+        .collect();
+
+    print_rows(out, rows, color, max_width)
+}
+
+/// Flush output that was written, returning the exit code to use, or failure if writing
+/// or flushing it failed.
+fn finish_output(written: io::Result<()>, out: &mut impl Write, code: ExitCode) -> ExitCode {
+    match written.and_then(|()| out.flush()) {
+        Ok(()) => code,
+        Err(e) => {
+            eprintln!("Error: Can't write output: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Summarize how many known folders are shown, and how many of their paths were obtained.
+fn summarize(count: usize, failed_count: usize) -> String {
+    let noun = if count == 1 { "folder" } else { "folders" };
+    let resolved_count = count - failed_count;
+    format!("{count} {noun}: {resolved_count} resolved, {failed_count} unavailable")
+}
+
+/// Use the `IKnownFolder` API to retrieve information, and print it in the given format.
+///
+/// This returns the exit code to use, unless it fails with an error from Windows that
+/// prevented it from listing known folders at all.
+fn run(kf_manager: &IKnownFolderManager, options: Options) -> Result<ExitCode, WindowsError> {
+    let _ui_language = match &options.locale {
+        Some(locale) => match UiLanguageOverride::new(locale) {
+            Ok(ui_language) => Some(ui_language),
+            Err(e) => {
+                eprintln!("Error: Can't use UI language {locale}: {}", e.message());
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        None => None,
+    };
+
+    if options.show_flags {
+        eprintln!("{}", describe_flags(options.flags));
+    }
+
+    if options.explain {
+        eprintln!("{}\n", explain_flags(options.flags));
+    }
+
+    let mut out = match open_output(options.output.as_deref(), options.encoding) {
+        Ok(out) => out,
+        Err(e) => {
+            let path = options.output.as_deref().unwrap_or(Path::new("-"));
+            eprintln!("Error: Can't open {path:?} for writing: {e}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    // This comes after opening the output file, so that file is opened as ourselves.
+    let _impersonation = match &options.as_user {
+        Some(user) => match Impersonation::new(user) {
+            Ok(impersonation) => Some(impersonation),
+            Err(e) => {
+                eprintln!("Error: Can't impersonate user {user}: {e}");
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        None => None,
+    };
+
+    if options.count {
+        let count = get_known_folder_ids(kf_manager)?.len();
+        let written = writeln!(out, "{count}");
+        return Ok(finish_output(written, &mut out, ExitCode::SUCCESS));
+    }
+
+    if options.list_ids {
+        let mut guids: Vec<_> = get_known_folder_ids(kf_manager)?
+            .iter()
+            .map(guid_to_string)
+            .collect();
+        if options.sort.is_some_and(|key| key != SortKey::None) {
+            guids.sort();
+        }
+        let written = guids.iter().try_for_each(|guid| writeln!(out, "{guid}"));
+        return Ok(finish_output(written, &mut out, ExitCode::SUCCESS));
+    }
+
+    if options.stdin_guids {
+        let lines = match io::stdin().lines().collect::<io::Result<Vec<_>>>() {
+            Ok(lines) => lines,
+            Err(e) => {
+                eprintln!("Error: Can't read GUIDs from standard input: {e}");
+                return Ok(ExitCode::FAILURE);
+            }
+        };
+        let written =
+            print_resolved_guids(&mut out, kf_manager, &lines, &options.lookup(options.flags));
+        return Ok(finish_output(written, &mut out, ExitCode::SUCCESS));
+    }
+
+    if let Some(diff_flags) = options.diff {
+        let mut before = options.get_named_paths(kf_manager, diff_flags)?;
+        let mut after = options.get_named_paths(kf_manager, options.flags)?;
+        select_named_paths(&mut before, &options);
+        select_named_paths(&mut after, &options);
+        let color = use_color(options.color, options.output.is_none());
+        let written = print_diff(&mut out, before, after, color, options.max_width);
+        return Ok(finish_output(written, &mut out, ExitCode::SUCCESS));
+    }
+
+    if let Some(seconds) = options.watch {
+        return watch(&mut out, kf_manager, &options, Duration::from_secs(seconds));
+    }
+
+    if options.streams_ndjson() {
+        return stream_ndjson(&mut out, kf_manager, &options);
+    }
+
+    let mut named_paths = match (&options.name, &options.guid, &options.names_file) {
+        (Some(name), _, _) => {
+            match get_named_path_by_name(kf_manager, name, &options.lookup(options.flags)) {
+                Ok(named_path) => vec![named_path],
+                Err(e) => {
+                    eprintln!("Error: Can't get known folder {name:?}: {}", e.message());
+                    return Ok(ExitCode::from(NOT_FOUND));
+                }
+            }
+        }
+        (None, Some(id), _) => {
+            match get_named_path_by_id(kf_manager, id, &options.lookup(options.flags)) {
+                Ok(named_path) => vec![named_path],
+                Err(e) => {
+                    let guid = guid_to_string(id);
+                    eprintln!("Error: Can't get known folder {guid}: {}", e.message());
+                    return Ok(ExitCode::from(NOT_FOUND));
+                }
+            }
+        }
+        (None, None, Some(path)) => match read_names_file(path) {
+            Ok(names) => {
+                get_named_paths_by_names(kf_manager, &names, &options.lookup(options.flags))
+            }
+            Err(e) => {
+                eprintln!("Error: Can't read names from {path:?}: {e}");
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        (None, None, None) => options.get_named_paths(kf_manager, options.flags)?,
+    };
+
+    if options.path_only {
+        let np = named_paths
+            .pop()
+            .expect("Bug: Looking up a single folder somehow found none");
+        return match np.try_path {
+            Ok(path) => {
+                let written = writeln!(out, "{path}");
+                Ok(finish_output(written, &mut out, ExitCode::SUCCESS))
+            }
+            Err(e) => {
+                let name = &np.name;
+                eprintln!(
+                    "Error: Can't get path of known folder {name:?}: {}",
+                    describe_error(&e)
+                );
+                Ok(ExitCode::FAILURE)
+            }
+        };
+    }
+
+    let mut notes = select_named_paths(&mut named_paths, &options);
+
+    if let Some(path) = &options.which {
+        let target = comparable_path(path);
+        named_paths.retain(|np| matches!(&np.try_path, Ok(p) if comparable_path(p) == target));
+        if named_paths.is_empty() {
+            eprintln!("Error: No known folder has the path {path:?}");
+            return Ok(ExitCode::from(NOT_FOUND));
+        }
+    }
+
+    if options.show_aliases {
+        note_aliases(&named_paths, &mut notes);
+    }
+
+    if options.audit_profile {
+        // USERPROFILE is our own profile, which isn't that of a user we impersonate.
+        let profile = if options.as_user.is_some() {
+            let lookup = KnownFolder::by_id(kf_manager, &FOLDERID_Profile)
+                .and_then(|profile| profile.path(KF_FLAG_DEFAULT));
+            match lookup {
+                Ok(profile) => profile,
+                Err(e) => {
+                    eprintln!(
+                        "Error: Can't audit paths, because the user profile can't be found: {}",
+                        describe_error(&e)
+                    );
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+        } else {
+            let Some(profile) = std::env::var_os("USERPROFILE") else {
+                eprintln!("Error: Can't audit paths, because USERPROFILE is not set");
+                return Ok(ExitCode::FAILURE);
+            };
+            profile.to_string_lossy().into_owned()
+        };
+        let outside = note_outside_profile(&named_paths, &profile, &mut notes);
+        if !outside.is_empty() {
+            let count = outside.len();
+            let noun = if count == 1 {
+                "folder is"
+            } else {
+                "folders are"
+            };
+            eprintln!(
+                "Warning: {count} per-user {noun} outside the user profile {profile:?}: {}",
+                outside.join(", ")
+            );
+        }
+    }
+
+    // With this flag, the paths of redirected folders are their redirection targets.
+    if options
+        .flags
+        .contains(KF_FLAG_RETURN_FILTER_REDIRECTION_TARGET)
+    {
+        for np in named_paths.iter().filter(|np| np.redirected) {
+            let note = "redirection target".to_owned();
+            notes.entry(np.id).or_default().push(note);
+        }
+    }
+
+    sort_named_paths(&mut named_paths, options.sort.unwrap_or_default());
+
+    if let Some(count) = options.first {
+        named_paths.truncate(count);
+    }
+
+    // This compares the folders that would be shown, so it selects them as --save does.
+    if let Some(path) = &options.compare {
+        let saved = match load_snapshot(path) {
+            Ok(saved) => saved,
+            Err(e) => {
+                eprintln!("Error: Can't load snapshot from {path:?}: {e}");
+                return Ok(ExitCode::FAILURE);
+            }
+        };
+        let color = use_color(options.color, options.output.is_none());
+        let written = print_comparison(&mut out, saved, named_paths, color, options.max_width);
+        return Ok(finish_output(written, &mut out, ExitCode::SUCCESS));
+    }
+
+    if let Some(path) = &options.save {
+        if let Err(e) = save_snapshot(path, &named_paths) {
+            eprintln!("Error: Can't save snapshot to {path:?}: {e}");
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+
+    let failed_count = named_paths.iter().filter(|np| np.try_path.is_err()).count();
+    let summary = summarize(named_paths.len(), failed_count);
+    let any_failed = failed_count != 0;
+
+    let written = match options.format {
+        _ if options.null_sep => print_null_separated(&mut out, named_paths),
+        _ if options.paths_only => print_paths(&mut out, named_paths),
+        Format::Table => print_table(&mut out, named_paths, &options, &notes),
+        Format::Json => print_json(&mut out, named_paths, true, options.verbose),
+        Format::JsonCompact => print_json(&mut out, named_paths, false, options.verbose),
+        Format::Ndjson => print_ndjson(&mut out, named_paths, options.verbose),
+        Format::Csv => print_csv(&mut out, named_paths),
+        Format::Tsv => print_tsv(&mut out, named_paths),
+        Format::Xml => print_xml(&mut out, named_paths),
+        Format::Markdown => print_markdown(&mut out, named_paths),
+        Format::Env => print_env(&mut out, named_paths),
+        Format::Kv => print_kv(&mut out, named_paths),
+        Format::Html => print_html(&mut out, named_paths),
+    };
+
+    let code = if options.strict && any_failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    };
+    let code = finish_output(written, &mut out, code);
+
+    // This goes to standard error, so it is not mixed into the table if that is piped.
+    if options.format == Format::Table && !options.null_sep && !options.paths_only && !options.quiet
+    {
+        eprintln!("{summary}");
+    }
+
+    Ok(code)
+}
+
+fn main() -> Result<ExitCode, WindowsError> {
+    // Parse arguments and bail out if we cannot proceed.
+    // (Returning the ArgError would show a less useful symbolic representation.)
+    let options = read_args().unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        std::process::exit(2);
+    });
+
+    if options.log_level != LogLevel::Off {
+        static LOGGER: StderrLogger = StderrLogger;
+        log::set_logger(&LOGGER).expect("Bug: A logger was somehow already set");
+        log::set_max_level(options.log_level.to_filter());
+    }
+
+    // To use `IKnownFolder`, we must have COM initialized on this thread.
+    let _com = match ComInit::new() {
+        Ok(com) => com,
+        Err(e) => return Ok(report_no_shell("COM initialization", &e)),
+    };
+
+    // Create the known folder manager once, and use it for every lookup.
+    let kf_manager = match create_known_folder_manager() {
+        Ok(kf_manager) => kf_manager,
+        Err(e) => return Ok(report_no_shell("Creating the known folder manager", &e)),
+    };
+
+    // Use those options to access the COM API for known folders and list them out.
+    run(&kf_manager, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A drive path longer than `MAX_PATH`, made of many nested directories.
+    fn long_drive_path() -> String {
+        format!(r"C:\Users\me{}", r"\subdirectory".repeat(20))
+    }
+
+    #[test]
+    fn extended_length_path_leaves_short_paths_unchanged() {
+        let path = r"C:\Users\me\Documents";
+        assert_eq!(extended_length_path(path), path);
+    }
+
+    #[test]
+    fn extended_length_path_prefixes_long_drive_paths() {
+        let path = long_drive_path();
+        assert!(path.len() > 260);
+        assert_eq!(extended_length_path(&path), format!(r"\\?\{path}"));
+    }
+
+    #[test]
+    fn extended_length_path_prefixes_long_unc_paths() {
+        let share = format!(r"server\share{}", r"\subdirectory".repeat(20));
+        let path = format!(r"\\{share}");
+        assert_eq!(extended_length_path(&path), format!(r"\\?\UNC\{share}"));
+    }
+
+    #[test]
+    fn extended_length_path_leaves_prefixed_paths_unchanged() {
+        let path = format!(r"\\?\{}", long_drive_path());
+        assert_eq!(extended_length_path(&path), path);
+    }
+
+    #[test]
+    fn extended_length_path_uses_backslashes_in_long_paths() {
+        let path = long_drive_path().replace('\\', "/");
+        assert_eq!(
+            extended_length_path(&path),
+            format!(r"\\?\{}", long_drive_path()),
+        );
+    }
+
+    #[test]
+    fn pad_to_width_counts_columns() {
+        assert_eq!(pad_to_width("abc", 5), "abc  ");
+        assert_eq!(pad_to_width("文書", 6), "文書  ");
+        assert_eq!(pad_to_width("abcdef", 3), "abcdef");
+    }
+
+    #[test]
+    fn pad_to_width_keeps_long_paths_whole() {
+        let path = long_drive_path();
+        assert_eq!(pad_to_width(&path, path.len() + 2), format!("{path}  "));
+    }
+
+    #[test]
+    fn truncate_to_width_keeps_text_that_fits() {
+        let path = long_drive_path();
+        assert_eq!(truncate_to_width(&path, path.len()), path);
+    }
+
+    #[test]
+    fn truncate_to_width_shortens_long_paths_with_ellipsis() {
+        let path = long_drive_path();
+        let truncated = truncate_to_width(&path, 100);
+        assert_eq!(truncated.width(), 100);
+        assert_eq!(truncated, format!("{}…", &path[..99]));
+    }
+
+    #[test]
+    fn truncate_to_width_does_not_split_wide_characters() {
+        assert_eq!(truncate_to_width("文書文書", 4), "文…");
+    }
+
+    #[test]
+    fn print_rows_shows_long_paths_in_full() {
+        let path = long_drive_path();
+        let rows = vec![Row {
+            cells: vec![
+                Cell::plain("Documents".to_owned()),
+                Cell::plain(path.clone()),
+            ],
+            details: vec![],
+        }];
+        let mut out = vec![];
+        print_rows(&mut out, rows, false, None).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("Documents  {path}\n")
+        );
+    }
 }